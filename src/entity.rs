@@ -3,49 +3,122 @@
 //! This system is responsible in loading, streaming and watching assets, also known as _entities_. Entities are
 //! independent objects identified by a unique identifier.
 
+pub mod cache;
 pub mod decoder;
 pub mod default_decoders;
+pub mod ignore;
 pub mod material;
 pub mod mesh;
 pub mod parameter;
+pub mod scene;
 pub mod shader;
 
-use self::{parameter::Parameter, shader::Shader};
+use self::{
+  cache::MeshCache,
+  ignore::{IgnoreRules, IGNORE_FILE_NAME},
+  material::Material,
+  parameter::Parameter,
+  scene::Node,
+  shader::Shader,
+};
 use crate::{
-  entity::decoder::HasDecoder,
+  entity::decoder::{
+    Decoded, Decoder, DecoderConflict, DecoderRegistry, DecodingError, DynDecoder, HasDecoder,
+  },
   proto::Kill,
   runtime::RuntimeMsg,
   system::{
     resource::Handle, resource::ResourceManager, system_init, Addr, MsgQueue, Publisher, Recipient,
-    System, SystemUID,
+    System, SystemError, SystemUID,
   },
 };
 use colored::Colorize as _;
 use mesh::Mesh;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher as _};
 use std::{
+  collections::{HashMap, HashSet},
+  env,
   ffi::OsStr,
-  fs::read_dir,
+  fmt,
+  fs::{self, read_dir},
   marker::PhantomData,
+  num::NonZeroUsize,
   path::{Path, PathBuf},
-  sync::Arc,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc, Arc, Mutex, PoisonError,
+  },
   thread,
+  time::{Duration, Instant, SystemTime},
 };
 
+/// Delay used to debounce filesystem notifications.
+///
+/// Editors tend to write files in several steps; waiting a bit allows to reload a resource only once it’s completely
+/// written.
+const WATCHER_DEBOUNCE_DELAY: Duration = Duration::from_millis(100);
+
+/// How long files loaded along with a newly created directory are remembered.
+///
+/// Their own notifications come within the debounce delay; past that, a notification is a genuine edit, even if the
+/// modification time didn’t change.
+const CREATED_FILE_LIFETIME: Duration =
+  Duration::from_millis(2 * WATCHER_DEBOUNCE_DELAY.as_millis() as u64);
+
 /// All possible entities.
 #[derive(Clone, Debug)]
 pub enum Entity {
   /// A [`Mesh`].
   Mesh(Arc<Mesh>),
+  /// A [`Material`].
+  Material(Arc<Material>),
+  /// A [`Node`].
+  Node(Arc<Node>),
   /// A [`Parameter`].
   Parameter(Arc<Parameter>),
   /// A [`Shader`].
   Shader(Arc<Shader>),
 }
 
+impl Entity {
+  /// Human-readable kind of entity.
+  pub fn kind(&self) -> &'static str {
+    match *self {
+      Entity::Mesh(_) => "mesh",
+      Entity::Material(_) => "material",
+      Entity::Node(_) => "node",
+      Entity::Parameter(_) => "parameter",
+      Entity::Shader(_) => "shader",
+    }
+  }
+}
+
 #[derive(Clone, Debug)]
 pub enum EntityMsg {
   /// Kill message.
   Kill,
+  /// Request an entity by its unique name.
+  ///
+  /// The entity system answers with [`EntityEvent::Loaded`] sent to `recipient`, either right away if the entity is
+  /// already loaded, or as soon as it gets loaded.
+  Request {
+    name: String,
+    recipient: EntityRecipient,
+  },
+  /// Subscribe to [`EntityEvent`]s while the system is running.
+  ///
+  /// If `replay` is `true`, an [`EntityEvent::Loaded`] is sent to `subscriber` for every entity that is already
+  /// loaded, in handle order.
+  Subscribe {
+    subscriber: EntityRecipient,
+    replay: bool,
+  },
+  /// A file was created or modified under the root directory.
+  FileChanged(PathBuf),
+  /// A directory was created under the root directory.
+  DirCreated(PathBuf),
+  /// A file or directory was removed from the root directory.
+  FileRemoved(PathBuf),
 }
 
 impl From<Kill> for EntityMsg {
@@ -54,6 +127,36 @@ impl From<Kill> for EntityMsg {
   }
 }
 
+/// Shared [`Recipient`] of [`EntityEvent`].
+///
+/// Used by other systems to get answers back from the entity system. Cloning it shares the same recipient.
+#[derive(Clone)]
+pub struct EntityRecipient(Arc<Mutex<dyn Recipient<EntityEvent>>>);
+
+impl EntityRecipient {
+  /// Wrap a recipient.
+  pub fn new(recipient: impl Recipient<EntityEvent> + 'static) -> Self {
+    // recipients are only required to be Send; the mutex allows to share them between clones
+    Self(Arc::new(Mutex::new(recipient)))
+  }
+}
+
+impl fmt::Debug for EntityRecipient {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    f.write_str("EntityRecipient")
+  }
+}
+
+impl Recipient<EntityEvent> for EntityRecipient {
+  fn send_msg(&self, msg: EntityEvent) -> Result<(), SystemError> {
+    self
+      .0
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .send_msg(msg)
+  }
+}
+
 /// Event the entity system can emit.
 #[derive(Clone, Debug)]
 pub enum EntityEvent {
@@ -62,6 +165,149 @@ pub enum EntityEvent {
     handle: Handle<Entity>,
     entity: Entity,
   },
+
+  /// An entity was removed, because the file it was decoded from disappeared.
+  Removed { handle: Handle<Entity> },
+
+  /// A file failed to decode.
+  ///
+  /// Entities previously decoded from that file, if any, are left untouched.
+  Failed {
+    /// Path of the file that failed to decode.
+    path: PathBuf,
+    /// Extension of the decoder that failed.
+    ext: &'static str,
+    /// Sub extension of the decoder that failed.
+    sub_ext: &'static str,
+    /// Error reported by the decoder.
+    reason: String,
+  },
+
+  /// A directory was traversed and all the files it contains were loaded.
+  Traversed {
+    /// Traversed directory.
+    root: PathBuf,
+    /// Number of files found in the directory.
+    file_count: usize,
+    /// Paths that were skipped because they couldn’t be read.
+    skipped: Vec<SkippedPath>,
+  },
+}
+
+/// A path that was skipped while traversing a directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SkippedPath {
+  pub path: PathBuf,
+  /// Why the path was skipped.
+  pub reason: String,
+}
+
+/// Recursive directory traversal.
+///
+/// Errors don’t stop the traversal: paths that cannot be read are recorded as [`SkippedPath`] and the traversal goes
+/// on with the rest of the directory.
+#[derive(Debug)]
+struct Traversal {
+  /// Files found so far, sorted by path in each directory.
+  files: Vec<PathBuf>,
+  skipped: Vec<SkippedPath>,
+  /// Canonical paths of the directories visited so far; used to detect symbolic link cycles.
+  visited_dirs: HashSet<PathBuf>,
+}
+
+impl Traversal {
+  fn new() -> Self {
+    Self {
+      files: Vec::new(),
+      skipped: Vec::new(),
+      visited_dirs: HashSet::new(),
+    }
+  }
+
+  fn skip(&mut self, path: impl Into<PathBuf>, reason: impl fmt::Display) {
+    let path = path.into();
+
+    log::warn!(
+      "skipping {}: {}",
+      path.display().to_string().purple().italic(),
+      reason
+    );
+
+    self.skipped.push(SkippedPath {
+      path,
+      reason: reason.to_string(),
+    });
+  }
+
+  /// Recursively collect all the files in a directory, leaving out ignored paths.
+  fn collect(&mut self, path: &Path, ignore_rules: &mut IgnoreRules) {
+    log::debug!(
+      "traversing {}",
+      path.display().to_string().purple().italic(),
+    );
+
+    match path.canonicalize() {
+      Ok(canonical_path) => {
+        if !self.visited_dirs.insert(canonical_path) {
+          self.skip(path, "directory already traversed (symbolic link cycle?)");
+          return;
+        }
+      }
+
+      Err(err) => {
+        self.skip(path, err);
+        return;
+      }
+    }
+
+    let dir_entries = match read_dir(path) {
+      Ok(dir_entries) => dir_entries,
+      Err(err) => {
+        self.skip(path, err);
+        return;
+      }
+    };
+
+    let mut paths = Vec::new();
+    for dir_entry in dir_entries {
+      match dir_entry {
+        Ok(dir_entry) => paths.push(dir_entry.path()),
+        Err(err) => self.skip(path, err),
+      }
+    }
+    paths.sort();
+
+    for path in paths {
+      // follow symbolic links; broken ones end up in the error case
+      let metadata = fs::metadata(&path);
+      let is_dir = metadata.as_ref().map(fs::Metadata::is_dir).unwrap_or(false);
+
+      if ignore_rules.is_ignored(&path, is_dir) {
+        log::debug!("ignoring {}", path.display().to_string().purple().italic());
+        continue;
+      }
+
+      match metadata {
+        Ok(metadata) if metadata.is_dir() => {
+          // recursively traverse this repository
+          self.collect(&path, ignore_rules);
+        }
+
+        Ok(metadata) if metadata.is_file() => {
+          log::debug!(
+            "found resource file {}",
+            path.display().to_string().purple().italic(),
+          );
+
+          self.files.push(path);
+        }
+
+        Ok(_) => self.skip(path, "not a regular file nor a directory"),
+
+        Err(err) => self.skip(path, err),
+      }
+    }
+  }
 }
 
 /// The [`Entity`] system.
@@ -74,30 +320,132 @@ pub struct EntitySystem<Decoders = default_decoders::Decoders> {
   addr: Addr<EntityMsg>,
   msg_queue: MsgQueue<EntityMsg>,
   publisher: EntityPublisher,
+  /// Decoders used to decode files, indexed by extensions.
+  decoders: DecoderRegistry,
+  /// Rules telling which paths under `root_dir` must not be loaded.
+  ignore_rules: IgnoreRules,
+  /// Requests for entities that are not loaded yet.
+  pending_requests: HashMap<Handle<Entity>, Vec<EntityRecipient>>,
+  /// Cache of decoded meshes, if enabled.
+  mesh_cache: Option<MeshCache>,
+  /// Files loaded along with a newly created directory, with their modification time at that moment and when they were
+  /// loaded.
+  created_files: HashMap<PathBuf, (SystemTime, Instant)>,
   _phantom: PhantomData<Decoders>,
 }
 
 impl<Decoders> EntitySystem<Decoders>
 where
-  Decoders: HasDecoder,
+  Decoders: 'static + HasDecoder,
 {
   /// Create a new [`EntitySystem`].
   pub fn new(runtime_addr: Addr<RuntimeMsg>, uid: SystemUID, root_dir: impl Into<PathBuf>) -> Self {
     let (addr, msg_queue) = system_init(uid);
     let root_dir = root_dir.into();
+    let mut decoders = DecoderRegistry::new();
+
+    if let Err(err) = Decoders::register_decoders(&mut decoders) {
+      log::error!("cannot register decoders: {}", err);
+    }
 
     Self {
       uid,
       runtime_addr,
       root_dir: root_dir.clone(),
-      resources: ResourceManager::new(root_dir),
+      resources: ResourceManager::new(root_dir.clone()),
+      ignore_rules: IgnoreRules::new(root_dir),
       addr,
       msg_queue,
       publisher: EntityPublisher::new(),
+      decoders,
+      pending_requests: HashMap::new(),
+      mesh_cache: None,
+      created_files: HashMap::new(),
       _phantom: PhantomData,
     }
   }
 
+  /// Cache decoded meshes in `dir`.
+  ///
+  /// Files decoding to meshes are loaded from the cache as long as their content doesn’t change.
+  pub fn enable_mesh_cache(&mut self, dir: impl Into<PathBuf>) {
+    let cache = MeshCache::new(dir);
+
+    log::info!(
+      "caching meshes in {}",
+      cache.dir().display().to_string().purple().italic()
+    );
+
+    self.mesh_cache = Some(cache);
+  }
+
+  /// Decode all the files under `root_dir` once, without registering, publishing nor watching anything.
+  ///
+  /// The files that cannot be decoded are returned along with their errors, so that broken resources can be caught in
+  /// CI. Unlike when the system runs, shaders failing [validation](Shader::validate) are reported as errors. The mesh
+  /// cache is neither read nor written.
+  pub fn check(&mut self) -> Vec<(PathBuf, DecodingError)> {
+    let mut traversal = Traversal::new();
+    traversal.collect(&self.root_dir, &mut self.ignore_rules);
+
+    let decoded = Self::decode_files(&self.decoders, &self.resources, None, &traversal.files);
+    let decoders = &self.decoders;
+
+    traversal
+      .files
+      .into_iter()
+      .zip(decoded)
+      .filter_map(|(path, decoded)| match decoded {
+        Some(Err(err)) => Some((path, err)),
+        Some(Ok(decoded)) => {
+          Self::validate_decoded(decoders, &path, &decoded).map(|err| (path, err))
+        }
+        None => None,
+      })
+      .collect()
+  }
+
+  /// Validate the shaders decoded from `path`, returning the issues of the first invalid one.
+  fn validate_decoded(
+    decoders: &DecoderRegistry,
+    path: &Path,
+    decoded: &[Decoded],
+  ) -> Option<DecodingError> {
+    let err = decoded.iter().find_map(|decoded| match decoded.entity {
+      Entity::Shader(ref shader) => shader.validate().err(),
+      _ => None,
+    })?;
+
+    // the file was decoded, so its decoder is known
+    let ext = path.extension().and_then(OsStr::to_str).unwrap_or("");
+    let sub_ext = Self::extract_sub_extension(path).unwrap_or("");
+    let (ext, sub_ext) = decoders
+      .find(ext, sub_ext)
+      .map_or(("", ""), |decoder| (decoder.ext(), decoder.sub_ext()));
+
+    Some(DecodingError::new(ext, sub_ext, err.to_string()))
+  }
+
+  /// Register an additional [`Decoder`].
+  ///
+  /// If a decoder is already registered for the same extensions, an error is returned.
+  pub fn register_decoder<D>(&mut self) -> Result<(), DecoderConflict>
+  where
+    D: 'static + Decoder,
+  {
+    self.decoders.register::<D>()
+  }
+
+  /// Register an additional [`DynDecoder`].
+  ///
+  /// If a decoder is already registered for the same extensions, an error is returned.
+  pub fn register_boxed_decoder(
+    &mut self,
+    decoder: Box<dyn DynDecoder>,
+  ) -> Result<(), DecoderConflict> {
+    self.decoders.register_boxed(decoder)
+  }
+
   /// Start the system.
   ///
   /// This method will first tries to load all the resources it can from `root_dir`, then will stay in an idle mode where it will:
@@ -108,9 +456,24 @@ where
     let root_dir = self.root_dir.clone(); // TODO: check how we can remove the clone
     self.traverse_directory(&root_dir);
 
+    // the watcher stops sending notifications as soon as it’s dropped, so keep it alive until we quit
+    let _watcher = self.watch_root_dir();
+
     // main loop
     loop {
       match self.msg_queue.recv() {
+        Some(EntityMsg::Request { name, recipient }) => self.request(name, recipient),
+
+        Some(EntityMsg::Subscribe { subscriber, replay }) => {
+          self.subscribe_while_running(subscriber, replay)
+        }
+
+        Some(EntityMsg::FileChanged(path)) => self.reload(&path),
+
+        Some(EntityMsg::DirCreated(path)) => self.load_created_dir(&path),
+
+        Some(EntityMsg::FileRemoved(path)) => self.remove(&path),
+
         Some(EntityMsg::Kill) | None => {
           self
             .runtime_addr
@@ -122,47 +485,546 @@ where
     }
   }
 
+  /// Load all the resources living in a directory.
+  ///
+  /// Files are decoded concurrently, and then registered and published in path order, so that handles are stable
+  /// for the same directory contents. Once done, an [`EntityEvent::Traversed`] summary is published.
   fn traverse_directory(&mut self, path: &Path) {
+    let mut traversal = Traversal::new();
+    traversal.collect(path, &mut self.ignore_rules);
+    self.load_traversal(path, traversal);
+  }
+
+  /// Load the files found by a traversal of `path`, then publish an [`EntityEvent::Traversed`] summary.
+  fn load_traversal(&mut self, path: &Path, traversal: Traversal) {
+    self.load_files(&traversal.files);
+
+    if traversal.skipped.is_empty() {
+      log::info!(
+        "traversed {}: {} files",
+        path.display().to_string().purple().italic(),
+        traversal.files.len()
+      );
+    } else {
+      log::warn!(
+        "traversed {}: {} files, {} skipped paths",
+        path.display().to_string().purple().italic(),
+        traversal.files.len(),
+        traversal.skipped.len()
+      );
+    }
+
+    let event = EntityEvent::Traversed {
+      root: path.to_owned(),
+      file_count: traversal.files.len(),
+      skipped: traversal.skipped,
+    };
+    self.publisher.publish(event);
+  }
+
+  /// Decode files concurrently, then register and publish the decoded entities in the order of `files`.
+  fn load_files(&mut self, files: &[PathBuf]) {
+    let decoded = Self::decode_files(
+      &self.decoders,
+      &self.resources,
+      self.mesh_cache.as_ref(),
+      files,
+    );
+
+    for (path, decoded) in files.iter().zip(decoded) {
+      if let Some(decoded) = decoded {
+        self.register_decoded(path, decoded);
+      }
+    }
+
+    self.answer_pending_requests();
+  }
+
+  /// Decode files on a pool of worker threads.
+  ///
+  /// The returned vector has one decoding result per file, in the same order as `files`.
+  fn decode_files(
+    decoders: &DecoderRegistry,
+    resources: &ResourceManager<Entity>,
+    cache: Option<&MeshCache>,
+    files: &[PathBuf],
+  ) -> Vec<Option<Result<Vec<Decoded>, DecodingError>>> {
+    let worker_count = thread::available_parallelism()
+      .map(NonZeroUsize::get)
+      .unwrap_or(1)
+      .min(files.len());
+    let next_file = AtomicUsize::new(0);
+
     log::debug!(
-      "traversing {}",
-      path.display().to_string().purple().italic(),
+      "decoding {} files with {} workers",
+      files.len(),
+      worker_count
     );
 
-    for dir_entry in read_dir(path).unwrap() {
-      let file = dir_entry.unwrap();
-      let path = file.path();
+    let next_file = &next_file;
+    let mut decoded: Vec<_> = files.iter().map(|_| None).collect();
 
-      if path.is_dir() {
-        // recursively traverse this repository
-        self.traverse_directory(&path);
-      } else if path.is_file() {
-        // invoke a handler for this file
-        log::debug!(
-          "found resource file {}",
-          path.display().to_string().purple().italic(),
-        );
+    thread::scope(|scope| {
+      let workers: Vec<_> = (0..worker_count)
+        .map(move |_| {
+          scope.spawn(move || {
+            let mut decoded = Vec::new();
+
+            // pick files until none is left
+            loop {
+              let index = next_file.fetch_add(1, Ordering::Relaxed);
+              let path = match files.get(index) {
+                Some(path) => path,
+                None => break,
+              };
 
-        // extract the extension
-        match path.extension().and_then(OsStr::to_str) {
-          Some(ext) => {
-            let sub_ext = Self::extract_sub_extension(&path).unwrap_or("");
-            self.extension_based_dispatch(ext, sub_ext, &path);
+              decoded.push((index, Self::decode_file(decoders, resources, cache, path)));
+            }
+
+            decoded
+          })
+        })
+        .collect();
+
+      // workers pick files in any order; put them back in the order of the input
+      for worker in workers {
+        match worker.join() {
+          Ok(worker_decoded) => {
+            for (index, file_decoded) in worker_decoded {
+              decoded[index] = file_decoded;
+            }
           }
 
-          None => {
+          // decoder panics are already caught by the registry; don’t let anything else take the system down
+          Err(_) => log::error!("decoding worker panicked; some files were not decoded"),
+        }
+      }
+    });
+
+    decoded
+  }
+
+  /// Watch `root_dir` and forward filesystem notifications to the system as [`EntityMsg`].
+  ///
+  /// The returned watcher must be kept alive for notifications to keep flowing.
+  fn watch_root_dir(&self) -> Option<RecommendedWatcher> {
+    let (sender, receiver) = mpsc::channel();
+
+    let mut watcher = match notify::watcher(sender, WATCHER_DEBOUNCE_DELAY) {
+      Ok(watcher) => watcher,
+      Err(err) => {
+        log::error!("cannot create filesystem watcher: {}", err);
+        return None;
+      }
+    };
+
+    if let Err(err) = watcher.watch(&self.root_dir, RecursiveMode::Recursive) {
+      log::error!(
+        "cannot watch {}: {}",
+        self.root_dir.display().to_string().purple().italic(),
+        err
+      );
+      return None;
+    }
+
+    log::info!(
+      "watching {}",
+      self.root_dir.display().to_string().purple().italic()
+    );
+
+    let root_dir = self.root_dir.clone();
+    let addr = self.addr.clone();
+    let _ = thread::spawn(move || {
+      let absolute_roots: Vec<PathBuf> = env::current_dir()
+        .map(|cwd| cwd.join(&root_dir))
+        .into_iter()
+        .chain(root_dir.canonicalize())
+        .collect();
+
+      // the watcher reports absolute paths, while resources are known by paths relative to the root directory
+      let relative_to_root = |path: PathBuf| {
+        absolute_roots
+          .iter()
+          .find_map(|absolute_root| path.strip_prefix(absolute_root).ok())
+          .map(|relative| root_dir.join(relative))
+          .unwrap_or(path)
+      };
+
+      for event in receiver {
+        let msg = match event {
+          DebouncedEvent::Create(path) if path.is_dir() => {
+            EntityMsg::DirCreated(relative_to_root(path))
+          }
+
+          // some platforms report writes to directories when their contents change; files report them on their own
+          DebouncedEvent::Write(path) if path.is_dir() => continue,
+
+          DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+            EntityMsg::FileChanged(relative_to_root(path))
+          }
+
+          DebouncedEvent::Remove(path) => EntityMsg::FileRemoved(relative_to_root(path)),
+
+          DebouncedEvent::Rename(from, to) => {
+            if addr
+              .send_msg(EntityMsg::FileRemoved(relative_to_root(from)))
+              .is_err()
+            {
+              break;
+            }
+
+            if to.is_dir() {
+              EntityMsg::DirCreated(relative_to_root(to))
+            } else {
+              EntityMsg::FileChanged(relative_to_root(to))
+            }
+          }
+
+          DebouncedEvent::Error(err, path) => {
             log::warn!(
-              "resource {} doesn’t have a path extension; ignoring",
-              path.display().to_string().purple().italic(),
+              "filesystem watcher error{}: {}",
+              path
+                .map(|path| format!(" for {}", path.display()))
+                .unwrap_or_default(),
+              err
             );
+            continue;
           }
+
+          _ => continue,
+        };
+
+        if addr.send_msg(msg).is_err() {
+          // the entity system is gone
+          break;
+        }
+      }
+    });
+
+    Some(watcher)
+  }
+
+  /// Load all the files of a directory that appeared on the filesystem.
+  ///
+  /// The files found are remembered along with their modification time, so that the creation notifications the
+  /// watcher also sends for them don’t decode them a second time.
+  fn load_created_dir(&mut self, path: &Path) {
+    if self.ignore_rules.is_ignored(path, true) {
+      return;
+    }
+
+    let mut traversal = Traversal::new();
+    traversal.collect(path, &mut self.ignore_rules);
+
+    let modified: Vec<_> = traversal
+      .files
+      .iter()
+      .filter_map(|file| {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        modified.ok().map(|modified| (file.clone(), modified))
+      })
+      .collect();
+
+    self.load_traversal(path, traversal);
+
+    let loaded_at = Instant::now();
+    self.created_files.extend(
+      modified
+        .into_iter()
+        .map(|(file, modified)| (file, (modified, loaded_at))),
+    );
+  }
+
+  /// Reload a file that has changed on the filesystem.
+  fn reload(&mut self, path: &Path) {
+    if self.update_ignore_rules(path) || self.ignore_rules.is_ignored(path, false) {
+      return;
+    }
+
+    // files of a newly created directory are already loaded, unless they were modified since then; their own
+    // notifications don’t come after the debounce delay, so forget about them past that
+    self
+      .created_files
+      .retain(|_, (_, loaded_at)| loaded_at.elapsed() < CREATED_FILE_LIFETIME);
+
+    if let Some((loaded_modified, _)) = self.created_files.remove(path) {
+      let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+
+      if modified.ok() == Some(loaded_modified) {
+        log::debug!(
+          "{} already loaded with its directory",
+          path.display().to_string().purple().italic()
+        );
+        return;
+      }
+    }
+
+    if path.is_file() {
+      // if the path is a dependency of other resources, reload the owners instead
+      let owner_sources = self.dependency_owner_sources(path);
+
+      if owner_sources.is_empty() {
+        log::info!(
+          "{} {}",
+          "reloading".yellow().bold(),
+          path.display().to_string().purple().italic()
+        );
+
+        self.load_files(&[path.to_owned()]);
+      } else {
+        for source in &owner_sources {
+          log::info!(
+            "{} {} because its dependency {} changed",
+            "reloading".yellow().bold(),
+            source.display().to_string().purple().italic(),
+            path.display().to_string().purple().italic()
+          );
+        }
+
+        self.load_files(&owner_sources);
+      }
+    }
+  }
+
+  /// Source paths of all the resources depending on `path`, without duplicates.
+  ///
+  /// Files that failed to decode while depending on `path` are included, so that fixing a dependency decodes them
+  /// again.
+  fn dependency_owner_sources(&self, path: &Path) -> Vec<PathBuf> {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let owner_sources = self
+      .resources
+      .dependency_owners(path)
+      .into_iter()
+      .filter_map(|handle| self.resources.source_path(handle).map(Path::to_owned));
+
+    for source in owner_sources.chain(self.resources.failed_dependents(path)) {
+      if !sources.contains(&source) {
+        sources.push(source);
+      }
+    }
+
+    sources
+  }
+
+  /// Subscribe a system to our events, and optionally replay all the entities that are already loaded.
+  fn subscribe_while_running(&mut self, subscriber: EntityRecipient, replay: bool) {
+    if replay {
+      let mut entities: Vec<_> = self.resources.iter().collect();
+      entities.sort_by_key(|&(handle, _)| handle);
+
+      log::debug!("replaying {} entities to new subscriber", entities.len());
+
+      for (handle, entity) in entities {
+        let event = EntityEvent::Loaded {
+          handle,
+          entity: entity.clone(),
+        };
+
+        if let Err(err) = subscriber.send_msg(event) {
+          log::warn!("cannot replay entity {} to new subscriber: {}", handle, err);
+          return;
+        }
+      }
+    }
+
+    self.publisher.subscribe(subscriber);
+  }
+
+  /// Answer a request for an entity, or delay the answer until the entity gets loaded.
+  fn request(&mut self, name: String, recipient: EntityRecipient) {
+    let handle = self.resources.reserve(&name);
+
+    match self.resources.get(handle) {
+      Some(entity) => {
+        let event = EntityEvent::Loaded {
+          handle,
+          entity: entity.clone(),
+        };
+
+        if let Err(err) = recipient.send_msg(event) {
+          log::warn!("cannot answer request for {}: {}", name.blue().bold(), err);
         }
       }
+
+      None => {
+        log::debug!(
+          "entity {} ({}) is not loaded yet; delaying request",
+          name.blue().bold(),
+          handle
+        );
+
+        self
+          .pending_requests
+          .entry(handle)
+          .or_insert_with(Vec::new)
+          .push(recipient);
+      }
+    }
+  }
+
+  /// Answer pending requests for entities that got loaded.
+  fn answer_pending_requests(&mut self) {
+    let resources = &self.resources;
+
+    self.pending_requests.retain(|&handle, recipients| {
+      let entity = match resources.get(handle) {
+        Some(entity) => entity,
+        None => return true,
+      };
+
+      for recipient in recipients.drain(..) {
+        let event = EntityEvent::Loaded {
+          handle,
+          entity: entity.clone(),
+        };
+
+        if let Err(err) = recipient.send_msg(event) {
+          log::warn!("cannot answer request for entity {}: {}", handle, err);
+        }
+      }
+
+      false
+    });
+  }
+
+  /// If `path` is an ignore file, apply the new rules of its directory and return `true`.
+  ///
+  /// Files that are not ignored anymore are loaded, and entities decoded from files that are now ignored are removed.
+  fn update_ignore_rules(&mut self, path: &Path) -> bool {
+    if path.file_name() != Some(OsStr::new(IGNORE_FILE_NAME)) {
+      return false;
+    }
+
+    log::info!(
+      "ignore rules changed at {}",
+      path.display().to_string().purple().italic()
+    );
+
+    if let Some(dir) = path.parent() {
+      self.ignore_rules.invalidate(dir);
+      self.reapply_ignore_rules(dir);
+    }
+
+    true
+  }
+
+  /// Traverse a directory which ignore rules changed, loading the files that are not ignored anymore and removing
+  /// the entities decoded from files that are now ignored.
+  fn reapply_ignore_rules(&mut self, dir: &Path) {
+    if !dir.is_dir() || self.ignore_rules.is_ignored(dir, true) {
+      return;
+    }
+
+    let mut traversal = Traversal::new();
+    traversal.collect(dir, &mut self.ignore_rules);
+
+    for handle in self.resources.sourced_from(dir) {
+      let source = match self.resources.source_path(handle) {
+        Some(source) => source.to_owned(),
+        None => continue,
+      };
+
+      if !traversal.files.contains(&source) {
+        self.remove_entity(handle, &source);
+      }
+    }
+
+    let new_files: Vec<_> = traversal
+      .files
+      .into_iter()
+      .filter(|file| self.resources.sourced_from(file).is_empty())
+      .collect();
+
+    if !new_files.is_empty() {
+      log::info!(
+        "loading {} files not ignored anymore in {}",
+        new_files.len(),
+        dir.display().to_string().purple().italic()
+      );
+
+      self.load_files(&new_files);
+    }
+  }
+
+  /// Remove all the entities decoded from a path that disappeared from the filesystem.
+  fn remove(&mut self, path: &Path) {
+    if self.update_ignore_rules(path) {
+      return;
+    }
+
+    for handle in self.resources.sourced_from(path) {
+      self.remove_entity(handle, path);
+    }
+
+    self.resources.forget_failed_path_deps(path);
+
+    // entities depending on the removed path might still be decodable without it
+    let mut owner_sources = self.dependency_owner_sources(path);
+    owner_sources.retain(|source| source.is_file());
+
+    if !owner_sources.is_empty() {
+      for source in &owner_sources {
+        log::info!(
+          "{} {} because its dependency {} was removed",
+          "reloading".yellow().bold(),
+          source.display().to_string().purple().italic(),
+          path.display().to_string().purple().italic()
+        );
+      }
+
+      self.load_files(&owner_sources);
+    }
+  }
+
+  /// Remove an entity decoded from `path` and publish its removal.
+  fn remove_entity(&mut self, handle: Handle<Entity>, path: &Path) {
+    if self.resources.remove(handle).is_some() {
+      log::info!(
+        "{} entity {} at {}",
+        "removed".red().bold(),
+        handle,
+        path.display().to_string().purple().italic()
+      );
+
+      self.publisher.publish(EntityEvent::Removed { handle });
     }
   }
 
-  /// Dispatch entity loading based on the extension of a file.
-  fn extension_based_dispatch(&mut self, ext: &str, sub_ext: &str, path: &Path) {
-    if !Decoders::load_from_file(&mut self.resources, &mut self.publisher, ext, sub_ext, path) {
+  /// Decode a file by looking at its extensions.
+  ///
+  /// If no decoder accepts the file, `None` is returned. When a mesh cache is provided, it is looked up first and
+  /// updated with freshly decoded meshes.
+  fn decode_file(
+    decoders: &DecoderRegistry,
+    resources: &ResourceManager<Entity>,
+    cache: Option<&MeshCache>,
+    path: &Path,
+  ) -> Option<Result<Vec<Decoded>, DecodingError>> {
+    let ext = match path.extension().and_then(OsStr::to_str) {
+      Some(ext) => ext,
+      None => {
+        log::warn!(
+          "resource {} doesn’t have a path extension; ignoring",
+          path.display().to_string().purple().italic(),
+        );
+
+        return None;
+      }
+    };
+    let sub_ext = Self::extract_sub_extension(path).unwrap_or("");
+
+    if let Some(decoded) = cache.and_then(|cache| cache.load(path)) {
+      return Some(Ok(decoded));
+    }
+
+    let decoded = decoders.load_from_file(resources, ext, sub_ext, path);
+
+    if let (Some(cache), Some(Ok(decoded))) = (cache, &decoded) {
+      cache.store(path, decoded);
+    }
+
+    if decoded.is_none() {
       if sub_ext.is_empty() {
         log::warn!(
           "unknown extension {} for path {}",
@@ -178,6 +1040,69 @@ where
         );
       }
     }
+
+    decoded
+  }
+
+  /// Register entities decoded from `path` and publish them, or publish the decoding error.
+  fn register_decoded(&mut self, path: &Path, decoded: Result<Vec<Decoded>, DecodingError>) {
+    let path_name = path.display().to_string().purple().italic();
+
+    match decoded {
+      Ok(decoded) => {
+        self.resources.forget_failed_path_deps(path);
+
+        // entities previously decoded from that file but not present anymore must be removed
+        let previous_handles = self.resources.sourced_from(path);
+        let mut handles = HashSet::new();
+
+        for Decoded {
+          name,
+          entity,
+          metadata,
+        } in decoded
+        {
+          let handle = self.resources.wrap(entity.clone(), &name, metadata);
+          handles.insert(handle);
+
+          log::debug!("assigned {} handle {}", name.blue().bold(), handle);
+          log::info!(
+            "{} {} {} at {}",
+            "loaded".green().bold(),
+            entity.kind(),
+            handle,
+            path_name
+          );
+
+          self
+            .publisher
+            .publish(EntityEvent::Loaded { handle, entity });
+        }
+
+        for handle in previous_handles {
+          if !handles.contains(&handle) {
+            self.remove_entity(handle, path);
+          }
+        }
+      }
+
+      Err(err) => {
+        log::error!("cannot load {}: {}", path_name, err);
+
+        // watch what the file depended on until it failed, so that fixing it decodes the file again
+        self
+          .resources
+          .register_failed_path_deps(path, err.path_deps.clone());
+
+        let event = EntityEvent::Failed {
+          path: path.to_owned(),
+          ext: err.ext,
+          sub_ext: err.sub_ext,
+          reason: err.reason,
+        };
+        self.publisher.publish(event);
+      }
+    }
   }
 
   fn extract_sub_extension(path: &Path) -> Option<&str> {
@@ -225,25 +1150,188 @@ impl<Decoders> Publisher<EntityEvent> for EntitySystem<Decoders> {
 
 /// Publisher of [`EntityEvent`].
 pub struct EntityPublisher {
-  subscribers: Vec<Box<dyn Recipient<EntityEvent>>>,
+  // behind a lock so that stopped subscribers can be dropped while publishing
+  subscribers: Mutex<Vec<Box<dyn Recipient<EntityEvent>>>>,
 }
 
 impl EntityPublisher {
   fn new() -> Self {
     Self {
-      subscribers: Vec::new(),
+      subscribers: Mutex::new(Vec::new()),
     }
   }
 }
 
 impl Publisher<EntityEvent> for EntityPublisher {
   fn subscribe(&mut self, subscriber: impl Recipient<EntityEvent> + 'static) {
-    self.subscribers.push(Box::new(subscriber));
+    self
+      .subscribers
+      .get_mut()
+      .unwrap_or_else(PoisonError::into_inner)
+      .push(Box::new(subscriber));
   }
 
   fn publish(&self, event: EntityEvent) {
-    for sub in &self.subscribers {
-      sub.send_msg(event.clone()).unwrap();
+    self
+      .subscribers
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .retain(|sub| match sub.send_msg(event.clone()) {
+        Ok(()) => true,
+
+        Err(err) => {
+          log::warn!(
+            "dropping subscriber that cannot receive events anymore: {}",
+            err
+          );
+          false
+        }
+      });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TempDir;
+
+  #[test]
+  fn publisher_drops_stopped_subscribers() {
+    let (running_addr, running_queue) = system_init::<EntityEvent>(SystemUID::new());
+    let (stopped_addr, stopped_queue) = system_init::<EntityEvent>(SystemUID::new());
+    let mut publisher = EntityPublisher::new();
+
+    publisher.subscribe(running_addr);
+    publisher.subscribe(stopped_addr);
+    drop(stopped_queue);
+
+    publisher.publish(EntityEvent::Removed {
+      handle: ResourceManager::<Entity>::new(".").reserve("foo"),
+    });
+
+    assert_eq!(publisher.subscribers.lock().unwrap().len(), 1);
+    assert!(matches!(
+      running_queue.recv(),
+      Some(EntityEvent::Removed { .. })
+    ));
+  }
+
+  #[test]
+  fn includes_reload_shaders_under_relative_root() {
+    // the default root directory is `.`, and the watcher reports paths under it as they are
+    let root = TempDir::new_in(Path::new("."), "relative-root");
+    assert!(root.starts_with("."));
+
+    fs::create_dir_all(root.join("lib")).unwrap();
+    fs::write(
+      root.join("lib/color.glsl"),
+      "vec4 white() { return vec4(1.); }\n",
+    )
+    .unwrap();
+    fs::write(
+      root.join("effect.glsl"),
+      "#version 330 core\n\n#pragma stage vertex\nvoid main() {\n  gl_Position = vec4(0.);\n}\n\n#pragma stage fragment\n#include \"lib/color.glsl\"\nout vec4 color;\nvoid main() {\n  color = white();\n}\n",
+    )
+    .unwrap();
+
+    let (runtime_addr, _runtime_queue) = system_init::<RuntimeMsg>(SystemUID::new());
+    let (addr, queue) = system_init::<EntityEvent>(SystemUID::new());
+    let mut system: EntitySystem =
+      EntitySystem::new(runtime_addr, SystemUID::new(), root.to_path_buf());
+    system.subscribe(addr);
+    system.traverse_directory(&root);
+    while queue.try_recv().is_some() {}
+
+    system.reload(&root.join("lib/color.glsl"));
+
+    let mut reloaded = Vec::new();
+    while let Some(event) = queue.try_recv() {
+      if let EntityEvent::Loaded {
+        entity: Entity::Shader(shader),
+        ..
+      } = event
+      {
+        reloaded.push(shader.name.clone());
+      }
     }
+
+    assert_eq!(
+      reloaded,
+      vec![root.join("effect.glsl").display().to_string()]
+    );
+  }
+
+  #[test]
+  fn created_files_reload_once_their_notifications_are_over() {
+    let shader = "#version 330 core\n\n#pragma stage vertex\nvoid main() {\n  gl_Position = vec4(0.);\n}\n\n#pragma stage fragment\nout vec4 color;\nvoid main() {\n  color = vec4(1.);\n}\n";
+    let root = TempDir::new("created-files");
+    let (runtime_addr, _runtime_queue) = system_init::<RuntimeMsg>(SystemUID::new());
+    let (addr, queue) = system_init::<EntityEvent>(SystemUID::new());
+    let mut system: EntitySystem =
+      EntitySystem::new(runtime_addr, SystemUID::new(), root.to_path_buf());
+    system.subscribe(addr);
+
+    let dir = root.join("new");
+    let path = dir.join("flat.glsl");
+    fs::create_dir(&dir).unwrap();
+    fs::write(&path, shader).unwrap();
+    system.load_created_dir(&dir);
+    while queue.try_recv().is_some() {}
+
+    // the notification of the file itself is already taken care of
+    system.reload(&path);
+    assert!(queue.try_recv().is_none());
+
+    // some files get no notification of their own; editing them later must reload them, even within the resolution of
+    // modification times
+    system.load_created_dir(&dir);
+    while queue.try_recv().is_some() {}
+    thread::sleep(CREATED_FILE_LIFETIME);
+
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    fs::write(&path, shader.replace("vec4(1.)", "vec4(0.)")).unwrap();
+    fs::File::options()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+    system.reload(&path);
+
+    assert!(matches!(
+      queue.try_recv(),
+      Some(EntityEvent::Loaded {
+        entity: Entity::Shader(_),
+        ..
+      })
+    ));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn traversal_skips_broken_paths() {
+    use std::os::unix::fs::symlink;
+
+    let root = TempDir::new("traversal");
+    fs::create_dir_all(root.join("a/b")).unwrap();
+    fs::write(root.join("a/b/mesh.obj"), "").unwrap();
+    fs::write(root.join("a/params.param.json"), "").unwrap();
+    symlink(root.join("a"), root.join("a/b/cycle")).unwrap();
+    symlink(root.join("missing"), root.join("broken")).unwrap();
+
+    fs::write(root.join(IGNORE_FILE_NAME), "*.swp\n").unwrap();
+    fs::write(root.join("a/b/.mesh.obj.swp"), "").unwrap();
+
+    let mut traversal = Traversal::new();
+    traversal.collect(&root, &mut IgnoreRules::new(root.to_path_buf()));
+
+    assert_eq!(
+      traversal.files,
+      vec![root.join("a/b/mesh.obj"), root.join("a/params.param.json")]
+    );
+
+    let mut skipped: Vec<_> = traversal.skipped.into_iter().map(|s| s.path).collect();
+    skipped.sort();
+    assert_eq!(skipped, vec![root.join("a/b/cycle"), root.join("broken")]);
   }
 }
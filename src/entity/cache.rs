@@ -0,0 +1,454 @@
+//! Binary mesh cache.
+//!
+//! Decoding text formats such as OBJ is slow for big meshes. Once decoded, meshes are written to a cache directory in
+//! a compact binary format, keyed by the path of their source and the hash of its content and of the content of its
+//! path dependencies, such as import settings. The next time the source is loaded, the cached meshes are used instead,
+//! as long as neither the source nor its dependencies have changed.
+//!
+//! Only files decoding exclusively to meshes are cached.
+//!
+//! The cache directory should live outside of the entity root directory; otherwise, it must be ignored with a
+//! `.spectraignore` file.
+//!
+//! # Format
+//!
+//! All numbers are little-endian.
+//!
+//! ```text
+//! magic           b"SPMC"
+//! version         u32
+//! source path     u32 length + UTF-8 bytes
+//! dependencies    u32 count + (u32 length + UTF-8 bytes) per path
+//! content hash    u64 (source, then every dependency; missing dependencies are hashed too)
+//! mesh count      u32
+//! meshes
+//!   name          u32 length + UTF-8 bytes
+//!   mode          u8
+//!   attributes    u8 (bit 0: uv, bit 1: tangent, bit 2: color)
+//!   vertices      u32 count + 16 f32 per vertex (position, normal, uv, tangent, color)
+//!   indices       u32 count + u32 per index
+//! ```
+
+use crate::entity::{
+  decoder::{Decoded, DecodingMetadata},
+  mesh::{Mesh, MeshIndex, MeshVertex, VertexAttributes},
+  Entity,
+};
+use colored::Colorize as _;
+use luminance::tess::Mode;
+use std::{
+  convert::TryInto as _,
+  fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+/// Magic number of cache entries.
+const MAGIC: &[u8; 4] = b"SPMC";
+
+/// Version of the cache format; bump it whenever the format or the way meshes are decoded changes.
+pub const MESH_CACHE_VERSION: u32 = 2;
+
+/// Extension of cache entries.
+const ENTRY_EXT: &str = "spmc";
+
+/// Cache of decoded meshes.
+#[derive(Clone, Debug)]
+pub struct MeshCache {
+  dir: PathBuf,
+}
+
+impl MeshCache {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self { dir: dir.into() }
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Path of the cache entry of a source.
+  fn entry_path(&self, path: &Path) -> PathBuf {
+    let key = fnv1a(FNV_OFFSET, path.display().to_string().as_bytes());
+    self.dir.join(format!("{:016x}.{}", key, ENTRY_EXT))
+  }
+
+  /// Load the meshes cached for `path`, if they’re still fresh.
+  pub fn load(&self, path: &Path) -> Option<Vec<Decoded>> {
+    let entry_path = self.entry_path(path);
+
+    // don’t bother hashing the source if nothing was cached
+    if !entry_path.is_file() {
+      return None;
+    }
+
+    let bytes = fs::read(&entry_path).ok()?;
+
+    match decode(&bytes, path, |deps| content_hash(path, deps).ok()) {
+      Ok(Some((deps, meshes))) => {
+        log::debug!(
+          "using cached meshes for {}",
+          path.display().to_string().purple().italic()
+        );
+
+        let decoded = meshes
+          .into_iter()
+          .map(|(name, mesh)| {
+            let mut metadata = DecodingMetadata::from_source(path);
+            for dep in &deps {
+              let _ = metadata.add_dep(dep.clone());
+            }
+
+            Decoded::new(name, Entity::Mesh(Arc::new(mesh)), metadata)
+          })
+          .collect();
+
+        Some(decoded)
+      }
+
+      Ok(None) => {
+        log::debug!(
+          "cached meshes for {} are stale",
+          path.display().to_string().purple().italic()
+        );
+        None
+      }
+
+      Err(reason) => {
+        log::warn!(
+          "ignoring corrupted cache entry {}: {}",
+          entry_path.display().to_string().purple().italic(),
+          reason
+        );
+        None
+      }
+    }
+  }
+
+  /// Store the meshes decoded from `path`.
+  ///
+  /// Nothing is stored if `decoded` contains something else than meshes. The path dependencies of all the meshes are
+  /// merged, so that every mesh loaded from the cache depends on all of them.
+  pub fn store(&self, path: &Path, decoded: &[Decoded]) {
+    let meshes: Option<Vec<_>> = decoded
+      .iter()
+      .map(|decoded| match decoded.entity {
+        Entity::Mesh(ref mesh) => Some((decoded.name.as_str(), &**mesh)),
+        _ => None,
+      })
+      .collect();
+
+    let meshes = match meshes {
+      Some(meshes) if !meshes.is_empty() => meshes,
+      _ => return,
+    };
+
+    let mut deps: Vec<_> = decoded
+      .iter()
+      .flat_map(|decoded| decoded.metadata.path_deps.iter().cloned())
+      .collect();
+    deps.sort();
+    deps.dedup();
+
+    let result = content_hash(path, &deps)
+      .and_then(|hash| {
+        encode(path, &deps, hash, &meshes).ok_or_else(|| {
+          std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unsupported primitive mode",
+          )
+        })
+      })
+      .and_then(|bytes| {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.entry_path(path), bytes)
+      });
+
+    match result {
+      Ok(()) => log::debug!(
+        "cached meshes for {}",
+        path.display().to_string().purple().italic()
+      ),
+
+      Err(err) => log::warn!(
+        "cannot cache meshes for {}: {}",
+        path.display().to_string().purple().italic(),
+        err
+      ),
+    }
+  }
+}
+
+/// Initial value of FNV-1a hashes.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a 64-bit hash, continuing from `hash`.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, byte| {
+    (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+  })
+}
+
+/// Hash of the content of a source and of its dependencies.
+///
+/// Dependencies don’t have to exist; creating one changes the hash.
+fn content_hash(path: &Path, deps: &[PathBuf]) -> std::io::Result<u64> {
+  let mut hash = fnv1a(FNV_OFFSET, &fs::read(path)?);
+
+  for dep in deps {
+    hash = fnv1a(hash, dep.display().to_string().as_bytes());
+    hash = match fs::read(dep) {
+      Ok(bytes) => fnv1a(fnv1a(hash, &[1]), &bytes),
+      Err(_) => fnv1a(hash, &[0]),
+    };
+  }
+
+  Ok(hash)
+}
+
+fn mode_to_u8(mode: Mode) -> Option<u8> {
+  match mode {
+    Mode::Point => Some(0),
+    Mode::Line => Some(1),
+    Mode::LineStrip => Some(2),
+    Mode::Triangle => Some(3),
+    Mode::TriangleStrip => Some(4),
+    Mode::TriangleFan => Some(5),
+    _ => None,
+  }
+}
+
+fn mode_from_u8(mode: u8) -> Option<Mode> {
+  match mode {
+    0 => Some(Mode::Point),
+    1 => Some(Mode::Line),
+    2 => Some(Mode::LineStrip),
+    3 => Some(Mode::Triangle),
+    4 => Some(Mode::TriangleStrip),
+    5 => Some(Mode::TriangleFan),
+    _ => None,
+  }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+  bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_str(bytes: &mut Vec<u8>, s: &str) {
+  push_u32(bytes, s.len() as u32);
+  bytes.extend_from_slice(s.as_bytes());
+}
+
+/// Encode meshes decoded from `path`; return `None` if a mesh cannot be represented.
+fn encode(path: &Path, deps: &[PathBuf], hash: u64, meshes: &[(&str, &Mesh)]) -> Option<Vec<u8>> {
+  let mut bytes = Vec::new();
+
+  bytes.extend_from_slice(MAGIC);
+  push_u32(&mut bytes, MESH_CACHE_VERSION);
+  push_str(&mut bytes, &path.display().to_string());
+
+  push_u32(&mut bytes, deps.len() as u32);
+  for dep in deps {
+    push_str(&mut bytes, &dep.display().to_string());
+  }
+
+  bytes.extend_from_slice(&hash.to_le_bytes());
+  push_u32(&mut bytes, meshes.len() as u32);
+
+  for &(name, mesh) in meshes {
+    let attributes = mesh.attributes();
+
+    push_str(&mut bytes, name);
+    bytes.push(mode_to_u8(mesh.mode())?);
+    bytes
+      .push(attributes.uv as u8 | (attributes.tangent as u8) << 1 | (attributes.color as u8) << 2);
+
+    push_u32(&mut bytes, mesh.vertices().len() as u32);
+    for vertex in mesh.vertices() {
+      let components = vertex
+        .pos()
+        .iter()
+        .chain(&vertex.nor())
+        .chain(&vertex.uv())
+        .chain(&vertex.tangent())
+        .chain(&vertex.color())
+        .copied()
+        .collect::<Vec<_>>();
+
+      for c in components {
+        bytes.extend_from_slice(&c.to_le_bytes());
+      }
+    }
+
+    push_u32(&mut bytes, mesh.indices().len() as u32);
+    for index in mesh.indices() {
+      bytes.extend_from_slice(&index.to_le_bytes());
+    }
+  }
+
+  Some(bytes)
+}
+
+/// Reader over the bytes of a cache entry.
+struct Reader<'a> {
+  bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+    if self.bytes.len() < n {
+      return Err("unexpected end of entry".to_owned());
+    }
+
+    let (taken, rest) = self.bytes.split_at(n);
+    self.bytes = rest;
+    Ok(taken)
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Result<u64, String> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn f32(&mut self) -> Result<f32, String> {
+    Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let len = self.u32()? as usize;
+    String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+  }
+}
+
+/// Decode a cache entry, along with the path dependencies of its meshes; return `None` if it’s stale.
+///
+/// `hash` computes the content hash of the source and the dependencies recorded in the entry.
+fn decode(
+  bytes: &[u8],
+  path: &Path,
+  hash: impl FnOnce(&[PathBuf]) -> Option<u64>,
+) -> Result<Option<(Vec<PathBuf>, Vec<(String, Mesh)>)>, String> {
+  let mut reader = Reader { bytes };
+
+  if reader.take(MAGIC.len())? != MAGIC {
+    return Err("not a mesh cache entry".to_owned());
+  }
+
+  // an entry written by another version or for another source (key collision) is stale
+  if reader.u32()? != MESH_CACHE_VERSION || reader.string()? != path.display().to_string() {
+    return Ok(None);
+  }
+
+  let dep_count = reader.u32()?;
+  let mut deps = Vec::new();
+  for _ in 0..dep_count {
+    deps.push(PathBuf::from(reader.string()?));
+  }
+
+  // so is an entry for an older content
+  if Some(reader.u64()?) != hash(&deps) {
+    return Ok(None);
+  }
+
+  let mesh_count = reader.u32()?;
+  let mut meshes = Vec::new();
+
+  for _ in 0..mesh_count {
+    let name = reader.string()?;
+    let mode = mode_from_u8(reader.u8()?).ok_or("unknown primitive mode")?;
+    let attributes_bits = reader.u8()?;
+    let attributes = VertexAttributes {
+      uv: attributes_bits & 1 != 0,
+      tangent: attributes_bits & 2 != 0,
+      color: attributes_bits & 4 != 0,
+    };
+
+    let vertex_count = reader.u32()?;
+    let mut vertices = Vec::new();
+    for _ in 0..vertex_count {
+      let mut c = [0.; 16];
+      for component in &mut c {
+        *component = reader.f32()?;
+      }
+
+      vertices.push(
+        MeshVertex::new([c[0], c[1], c[2]], [c[3], c[4], c[5]])
+          .with_uv([c[6], c[7]])
+          .with_tangent([c[8], c[9], c[10], c[11]])
+          .with_color([c[12], c[13], c[14], c[15]]),
+      );
+    }
+
+    let index_count = reader.u32()?;
+    let mut indices = Vec::new();
+    for _ in 0..index_count {
+      let index: MeshIndex = reader.u32()?;
+
+      if index >= vertex_count {
+        return Err(format!("vertex index {} out of bounds", index));
+      }
+
+      indices.push(index);
+    }
+
+    meshes.push((name, Mesh::new(vertices, indices, mode, attributes)));
+  }
+
+  Ok(Some((deps, meshes)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::entity::mesh::procedural::MeshDescription;
+
+  #[test]
+  fn roundtrip() {
+    let description: MeshDescription = serde_json::from_str(r#"{ "primitive": "torus" }"#).unwrap();
+    let mesh = description.build().unwrap();
+    let path = Path::new("assets/torus.mesh.json");
+    let deps = vec![PathBuf::from("assets/torus.mesh.json.import.json")];
+
+    let bytes = encode(path, &deps, 42, &[("torus", &mesh)]).unwrap();
+    let (decoded_deps, decoded) = decode(&bytes, path, |entry_deps| {
+      assert_eq!(entry_deps, deps.as_slice());
+      Some(42)
+    })
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(decoded_deps, deps);
+
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(decoded[0].0, "torus");
+    assert_eq!(decoded[0].1.indices(), mesh.indices());
+    assert_eq!(decoded[0].1.attributes(), mesh.attributes());
+    assert_eq!(decoded[0].1.aabb(), mesh.aabb());
+
+    for (a, b) in decoded[0].1.vertices().iter().zip(mesh.vertices()) {
+      assert_eq!(a.pos(), b.pos());
+      assert_eq!(a.nor(), b.nor());
+      assert_eq!(a.uv(), b.uv());
+      assert_eq!(a.tangent(), b.tangent());
+      assert_eq!(a.color(), b.color());
+    }
+
+    // another content or another source make the entry stale
+    assert!(decode(&bytes, path, |_| Some(43)).unwrap().is_none());
+    assert!(
+      decode(&bytes, Path::new("assets/other.mesh.json"), |_| Some(42))
+        .unwrap()
+        .is_none()
+    );
+
+    // truncated entries are corrupted
+    assert!(decode(&bytes[..bytes.len() - 1], path, |_| Some(42)).is_err());
+  }
+}
@@ -1,11 +1,16 @@
 //! Resource decoders.
 
-use crate::{
-  entity::{Entity, EntityEvent},
-  system::{resource::ResourceManager, Publisher},
+use crate::{entity::Entity, system::resource::ResourceManager};
+use std::{
+  any::Any,
+  collections::{HashMap, HashSet},
+  error::Error,
+  fmt,
+  marker::PhantomData,
+  panic::{self, AssertUnwindSafe},
+  path::Path,
+  path::PathBuf,
 };
-use colored::Colorize as _;
-use std::{collections::HashSet, error::Error, path::Path, path::PathBuf};
 
 /// Resource decoder.
 pub trait Decoder: Sized {
@@ -20,27 +25,119 @@ pub trait Decoder: Sized {
   /// Decoder error.
   type Err: Error;
 
-  /// Load a resource from a path.
+  /// Load resources from a path.
+  ///
+  /// Decoders don’t register what they decode: they return [`Decoded`] entities, which are then registered and
+  /// published by the entity system. This allows to run several decoders concurrently.
   fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
-    publisher: &mut impl Publisher<EntityEvent>,
+    resources: &ResourceManager<Entity>,
     path: impl AsRef<Path>,
-  ) -> Result<(), Self::Err>;
+  ) -> Result<Vec<Decoded>, Self::Err>;
+
+  /// Paths a failed decoding depended on.
+  ///
+  /// Those paths are watched like the dependencies of decoded entities: when one of them changes, the file is decoded
+  /// again. The default implementation returns no path.
+  fn error_path_deps(_err: &Self::Err) -> HashSet<PathBuf> {
+    HashSet::new()
+  }
 }
 
-/// Information passed around after decoding to trace dependencies and other kind of data.
+/// An entity freshly decoded by a [`Decoder`], yet to be registered.
 #[derive(Debug)]
+pub struct Decoded {
+  /// Unique name of the entity.
+  pub name: String,
+  /// The decoded entity.
+  pub entity: Entity,
+  /// Metadata to register along with the entity.
+  pub metadata: DecodingMetadata,
+}
+
+impl Decoded {
+  pub fn new(name: impl Into<String>, entity: Entity, metadata: DecodingMetadata) -> Self {
+    Self {
+      name: name.into(),
+      entity,
+      metadata,
+    }
+  }
+}
+
+/// Error that occurred while a [`Decoder`] was decoding a file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DecodingError {
+  /// Extension of the decoder that failed.
+  pub ext: &'static str,
+  /// Sub extension of the decoder that failed.
+  pub sub_ext: &'static str,
+  /// Error reported by the decoder.
+  pub reason: String,
+  /// Paths the decoding depended on up to the failure.
+  pub path_deps: HashSet<PathBuf>,
+}
+
+impl DecodingError {
+  pub fn new(ext: &'static str, sub_ext: &'static str, reason: impl Into<String>) -> Self {
+    Self {
+      ext,
+      sub_ext,
+      reason: reason.into(),
+      path_deps: HashSet::new(),
+    }
+  }
+
+  /// Set the paths the decoding depended on up to the failure.
+  pub fn with_path_deps(mut self, path_deps: HashSet<PathBuf>) -> Self {
+    self.path_deps = path_deps;
+    self
+  }
+
+  fn from_decoder_err<D>(err: D::Err) -> Self
+  where
+    D: Decoder,
+  {
+    let path_deps = D::error_path_deps(&err);
+    Self::new(D::EXT, D::SUB_EXT, err.to_string()).with_path_deps(path_deps)
+  }
+}
+
+impl fmt::Display for DecodingError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    self.reason.fmt(f)
+  }
+}
+
+impl Error for DecodingError {}
+
+/// Information passed around after decoding to trace dependencies and other kind of data.
+#[derive(Clone, Debug)]
 pub struct DecodingMetadata {
+  /// Path of the file the resource was decoded from.
+  pub source: Option<PathBuf>,
   pub path_deps: HashSet<PathBuf>,
 }
 
 impl DecodingMetadata {
   pub fn new() -> Self {
     Self {
+      source: None,
       path_deps: HashSet::new(),
     }
   }
 
+  /// Shortcut to create a [`DecodingMetadata`] for a resource decoded from `path`.
+  pub fn from_source(path: impl Into<PathBuf>) -> Self {
+    let mut dmd = Self::new();
+    dmd.set_source(path);
+    dmd
+  }
+
+  /// Set the path of the file the resource was decoded from.
+  pub fn set_source(&mut self, path: impl Into<PathBuf>) {
+    self.source = Some(path.into());
+  }
+
   /// Add a dependency.
   ///
   /// If the dependency was not already present, return `true`, `false otherwise`.
@@ -60,42 +157,179 @@ impl DecodingMetadata {
   }
 }
 
-/// Type that contains decoders.
-pub trait HasDecoder {
+/// Object-safe version of [`Decoder`].
+///
+/// This is the trait to implement to register decoders at runtime in a [`DecoderRegistry`]. Any [`Decoder`] can be
+/// registered as a [`DynDecoder`] via [`DecoderRegistry::register`].
+pub trait DynDecoder: Send + Sync {
+  /// File extension this decoder accepts.
+  fn ext(&self) -> &'static str;
+
+  /// File sub extension this decoder accepts.
+  fn sub_ext(&self) -> &'static str;
+
+  /// Load resources from a path.
   fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
-    publisher: &mut impl Publisher<EntityEvent>,
-    ext: impl AsRef<str>,
-    sub_ext: impl AsRef<str>,
-    path: impl AsRef<Path>,
-  ) -> bool;
+    &self,
+    resources: &ResourceManager<Entity>,
+    path: &Path,
+  ) -> Result<Vec<Decoded>, DecodingError>;
 }
 
-impl<D> HasDecoder for D
+/// [`DynDecoder`] implementation for a [`Decoder`].
+struct StaticDecoder<D>(PhantomData<fn() -> D>);
+
+impl<D> DynDecoder for StaticDecoder<D>
 where
   D: Decoder,
 {
+  fn ext(&self) -> &'static str {
+    D::EXT
+  }
+
+  fn sub_ext(&self) -> &'static str {
+    D::SUB_EXT
+  }
+
   fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
-    publisher: &mut impl Publisher<EntityEvent>,
-    ext: impl AsRef<str>,
-    sub_ext: impl AsRef<str>,
-    path: impl AsRef<Path>,
-  ) -> bool {
-    let path = path.as_ref();
-    let was_active = ext.as_ref() == D::EXT && sub_ext.as_ref() == D::SUB_EXT;
-
-    if was_active {
-      if let Err(err) = <D as Decoder>::load_from_file(resources, publisher, path) {
-        log::error!(
-          "cannot load {}: {}",
-          path.display().to_string().purple().italic(),
-          err,
-        );
-      }
+    &self,
+    resources: &ResourceManager<Entity>,
+    path: &Path,
+  ) -> Result<Vec<Decoded>, DecodingError> {
+    D::load_from_file(resources, path).map_err(DecodingError::from_decoder_err::<D>)
+  }
+}
+
+/// Decoders registered at runtime, indexed by the extensions they accept.
+pub struct DecoderRegistry {
+  /// Decoders, indexed by extension and then by sub extension.
+  decoders: HashMap<&'static str, HashMap<&'static str, Box<dyn DynDecoder>>>,
+}
+
+impl DecoderRegistry {
+  /// Create an empty [`DecoderRegistry`].
+  pub fn new() -> Self {
+    Self {
+      decoders: HashMap::new(),
+    }
+  }
+
+  /// Register a [`Decoder`].
+  pub fn register<D>(&mut self) -> Result<(), DecoderConflict>
+  where
+    D: 'static + Decoder,
+  {
+    self.register_boxed(Box::new(StaticDecoder::<D>(PhantomData)))
+  }
+
+  /// Register a [`DynDecoder`].
+  ///
+  /// If a decoder is already registered for the same extensions, the registry is left untouched and an error is
+  /// returned.
+  pub fn register_boxed(&mut self, decoder: Box<dyn DynDecoder>) -> Result<(), DecoderConflict> {
+    let ext = decoder.ext();
+    let sub_ext = decoder.sub_ext();
+    let sub_decoders = self.decoders.entry(ext).or_insert_with(HashMap::new);
+
+    if sub_decoders.contains_key(sub_ext) {
+      return Err(DecoderConflict { ext, sub_ext });
+    }
+
+    let _ = sub_decoders.insert(sub_ext, decoder);
+    Ok(())
+  }
+
+  /// Check whether a decoder is registered for the given extensions.
+  pub fn contains(&self, ext: &str, sub_ext: &str) -> bool {
+    self.find(ext, sub_ext).is_some()
+  }
+
+  /// Decode a file with the decoder accepting `ext` and `sub_ext`.
+  ///
+  /// If no decoder accepts those extensions, `None` is returned. A decoder panicking, typically on a malformed file,
+  /// is reported as a [`DecodingError`].
+  pub fn load_from_file(
+    &self,
+    resources: &ResourceManager<Entity>,
+    ext: &str,
+    sub_ext: &str,
+    path: &Path,
+  ) -> Option<Result<Vec<Decoded>, DecodingError>> {
+    self.find(ext, sub_ext).map(|decoder| {
+      panic::catch_unwind(AssertUnwindSafe(|| decoder.load_from_file(resources, path)))
+        .unwrap_or_else(|payload| {
+          Err(DecodingError::new(
+            decoder.ext(),
+            decoder.sub_ext(),
+            format!("decoder panicked: {}", panic_reason(&*payload)),
+          ))
+        })
+    })
+  }
+
+  /// Find the decoder accepting `ext` and `sub_ext`, if any.
+  pub(crate) fn find(&self, ext: &str, sub_ext: &str) -> Option<&dyn DynDecoder> {
+    self
+      .decoders
+      .get(ext)
+      .and_then(|sub_decoders| sub_decoders.get(sub_ext))
+      .map(|decoder| &**decoder)
+  }
+}
+
+impl Default for DecoderRegistry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Message of a panic, if it has one.
+fn panic_reason(payload: &(dyn Any + Send)) -> &str {
+  payload
+    .downcast_ref::<&str>()
+    .copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("unknown reason")
+}
+
+/// Error returned when registering a decoder for extensions already claimed by another decoder.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DecoderConflict {
+  pub ext: &'static str,
+  pub sub_ext: &'static str,
+}
+
+impl fmt::Display for DecoderConflict {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    if self.sub_ext.is_empty() {
+      write!(f, "a decoder is already registered for {}", self.ext)
+    } else {
+      write!(
+        f,
+        "a decoder is already registered for {}.{}",
+        self.sub_ext, self.ext
+      )
     }
+  }
+}
+
+impl Error for DecoderConflict {}
 
-    was_active
+/// Type that contains decoders.
+///
+/// This is implemented for any [`Decoder`] and for tuples of [`HasDecoder`], allowing to build a set of decoders at
+/// compile-time.
+pub trait HasDecoder {
+  /// Register all the decoders in a [`DecoderRegistry`].
+  fn register_decoders(registry: &mut DecoderRegistry) -> Result<(), DecoderConflict>;
+}
+
+impl<D> HasDecoder for D
+where
+  D: 'static + Decoder,
+{
+  fn register_decoders(registry: &mut DecoderRegistry) -> Result<(), DecoderConflict> {
+    registry.register::<D>()
   }
 }
 
@@ -114,21 +348,82 @@ macro_rules! impl_has_decoder_tuple {
     where
       $($t: HasDecoder),*
     {
-      fn load_from_file(
-        resources: &mut ResourceManager<Entity>,
-        publisher: &mut impl Publisher<EntityEvent>,
-        ext: impl AsRef<str>,
-        sub_ext: impl AsRef<str>,
-        path: impl AsRef<Path>,
-      ) -> bool {
-        let ext = ext.as_ref();
-        let sub_ext = sub_ext.as_ref();
-        let path = path.as_ref();
-
-        $( $t::load_from_file(resources, publisher, ext, sub_ext, path) || )* false
+      fn register_decoders(registry: &mut DecoderRegistry) -> Result<(), DecoderConflict> {
+        $( $t::register_decoders(registry)?; )*
+        Ok(())
       }
     }
   }
 }
 
 impl_has_decoder_tuples!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::entity::{default_decoders::Decoders, mesh::OBJDecoder, parameter::ParameterDecoder};
+
+  #[test]
+  fn registry_conflicts() {
+    let mut registry = DecoderRegistry::new();
+
+    assert_eq!(Decoders::register_decoders(&mut registry), Ok(()));
+    assert!(registry.contains("obj", ""));
+    assert!(registry.contains("json", "param"));
+    assert!(registry.contains("json", "shd"));
+    assert!(!registry.contains("json", ""));
+
+    assert_eq!(
+      registry.register::<OBJDecoder>(),
+      Err(DecoderConflict {
+        ext: "obj",
+        sub_ext: ""
+      })
+    );
+    assert_eq!(
+      registry.register::<ParameterDecoder>(),
+      Err(DecoderConflict {
+        ext: "json",
+        sub_ext: "param"
+      })
+    );
+  }
+
+  #[test]
+  fn panicking_decoder() {
+    struct PanickingDecoder;
+
+    impl Decoder for PanickingDecoder {
+      const EXT: &'static str = "bin";
+
+      const SUB_EXT: &'static str = "broken";
+
+      type Err = DecodingError;
+
+      fn load_from_file(
+        _: &ResourceManager<Entity>,
+        _: impl AsRef<Path>,
+      ) -> Result<Vec<Decoded>, Self::Err> {
+        panic!("index out of bounds")
+      }
+    }
+
+    let mut registry = DecoderRegistry::new();
+    registry.register::<PanickingDecoder>().unwrap();
+
+    let err = registry
+      .load_from_file(
+        &ResourceManager::new("."),
+        "bin",
+        "broken",
+        Path::new("foo.broken.bin"),
+      )
+      .unwrap()
+      .unwrap_err();
+
+    assert_eq!(
+      err,
+      DecodingError::new("bin", "broken", "decoder panicked: index out of bounds")
+    );
+  }
+}
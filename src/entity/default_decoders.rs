@@ -1,5 +1,21 @@
 //! Default decoders.
 
-use crate::entity::{mesh::OBJDecoder, parameter::ParameterDecoder, shader::JSONShaderDecoder};
+use crate::entity::{
+  mesh::{ImportSettingsDecoder, OBJDecoder, PLYDecoder, ProceduralMeshDecoder, STLDecoder},
+  parameter::ParameterDecoder,
+  scene::{GLBDecoder, GLTFDecoder},
+  shader::{GLSLShaderDecoder, JSONShaderDecoder},
+};
 
-pub type Decoders = (OBJDecoder, ParameterDecoder, JSONShaderDecoder);
+pub type Decoders = (
+  OBJDecoder,
+  PLYDecoder,
+  STLDecoder,
+  ProceduralMeshDecoder,
+  ImportSettingsDecoder,
+  GLTFDecoder,
+  GLBDecoder,
+  ParameterDecoder,
+  JSONShaderDecoder,
+  GLSLShaderDecoder,
+);
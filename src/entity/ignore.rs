@@ -0,0 +1,386 @@
+//! Ignore rules for the entity root directory.
+//!
+//! Any directory under the root directory can contain a `.spectraignore` file, listing gitignore-style patterns of
+//! paths the entity system must not load nor watch. Patterns are relative to the directory containing the
+//! `.spectraignore` file and apply to its whole subtree:
+//!
+//! - Blank lines and lines starting with `#` are ignored.
+//! - `*` matches anything but `/`, `?` matches any single character but `/` and `[…]` matches a character class.
+//! - `**` matches anything, including `/`; `**/` matches zero or more directories.
+//! - A pattern without any `/` (but a trailing one) matches file names at any depth. Otherwise, it is matched
+//!   against the path relative to the directory containing the `.spectraignore` file.
+//! - A trailing `/` only matches directories.
+//! - A leading `!` negates the pattern, re-including what a previous pattern ignored.
+//!
+//! Later patterns override earlier ones, and patterns from nested directories override the ones from their parents.
+//! Ignoring a directory ignores everything it contains.
+
+use colored::Colorize as _;
+use std::{
+  collections::HashMap,
+  ffi::OsStr,
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+/// Name of the files containing ignore rules.
+pub const IGNORE_FILE_NAME: &str = ".spectraignore";
+
+/// Ignore rules of a root directory.
+///
+/// Rules are read from `.spectraignore` files lazily, and cached until [`IgnoreRules::invalidate`] is called.
+#[derive(Debug)]
+pub struct IgnoreRules {
+  root_dir: PathBuf,
+  /// Patterns declared in each directory.
+  patterns: HashMap<PathBuf, Vec<Pattern>>,
+}
+
+impl IgnoreRules {
+  /// Create the ignore rules of `root_dir`.
+  pub fn new(root_dir: impl Into<PathBuf>) -> Self {
+    Self {
+      root_dir: root_dir.into(),
+      patterns: HashMap::new(),
+    }
+  }
+
+  /// Forget the patterns of a directory, so that its `.spectraignore` file is read again.
+  pub fn invalidate(&mut self, dir: impl AsRef<Path>) {
+    let _ = self.patterns.remove(dir.as_ref());
+  }
+
+  /// Check whether a path must be ignored.
+  ///
+  /// `is_dir` tells whether `path` is a directory. `.spectraignore` files are always ignored, as well as paths that
+  /// live in an ignored directory. Paths outside of the root directory are never ignored.
+  pub fn is_ignored(&mut self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+    let path = path.as_ref();
+
+    if path.file_name() == Some(OsStr::new(IGNORE_FILE_NAME)) {
+      return true;
+    }
+
+    let relative_path = match path.strip_prefix(&self.root_dir) {
+      Ok(relative_path) => relative_path.to_owned(),
+      Err(_) => return false,
+    };
+
+    // check every ancestor, since ignoring a directory ignores everything in it
+    let components: Vec<_> = relative_path.components().collect();
+    (1..=components.len()).any(|len| {
+      let ancestor: PathBuf = components[..len].iter().collect();
+      let is_ancestor_dir = len < components.len() || is_dir;
+      self.matches(&ancestor, is_ancestor_dir)
+    })
+  }
+
+  /// Check a path relative to the root directory against the patterns of every directory containing it.
+  fn matches(&mut self, relative_path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    let mut dir = PathBuf::new();
+
+    for component in relative_path
+      .parent()
+      .into_iter()
+      .flat_map(Path::components)
+    {
+      ignored = self.matches_in_dir(&dir, relative_path, is_dir, ignored);
+      dir.push(component);
+    }
+
+    self.matches_in_dir(&dir, relative_path, is_dir, ignored)
+  }
+
+  /// Apply the patterns of `dir` (relative to the root directory) to `relative_path`.
+  fn matches_in_dir(
+    &mut self,
+    dir: &Path,
+    relative_path: &Path,
+    is_dir: bool,
+    mut ignored: bool,
+  ) -> bool {
+    let path_in_dir = match relative_path.strip_prefix(dir) {
+      Ok(path_in_dir) => path_in_dir,
+      Err(_) => return ignored,
+    };
+
+    for pattern in self.patterns_of(dir) {
+      if pattern.matches(path_in_dir, is_dir) {
+        ignored = !pattern.negated;
+      }
+    }
+
+    ignored
+  }
+
+  /// Patterns declared in a directory (relative to the root directory).
+  fn patterns_of(&mut self, dir: &Path) -> &[Pattern] {
+    let dir = self.root_dir.join(dir);
+
+    if !self.patterns.contains_key(&dir) {
+      let patterns = Self::read_patterns(&dir);
+      let _ = self.patterns.insert(dir.clone(), patterns);
+    }
+
+    &self.patterns[&dir]
+  }
+
+  fn read_patterns(dir: &Path) -> Vec<Pattern> {
+    let path = dir.join(IGNORE_FILE_NAME);
+
+    match fs::read_to_string(&path) {
+      Ok(content) => {
+        log::debug!(
+          "reading ignore rules from {}",
+          path.display().to_string().purple().italic()
+        );
+
+        content.lines().filter_map(Pattern::parse).collect()
+      }
+
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+
+      Err(err) => {
+        log::warn!(
+          "cannot read ignore rules from {}: {}",
+          path.display().to_string().purple().italic(),
+          err
+        );
+
+        Vec::new()
+      }
+    }
+  }
+}
+
+/// A single gitignore-style pattern.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Pattern {
+  glob: Vec<char>,
+  /// Re-include paths instead of ignoring them.
+  negated: bool,
+  /// Only match directories.
+  dir_only: bool,
+  /// Match the whole relative path instead of the file name.
+  anchored: bool,
+}
+
+impl Pattern {
+  /// Parse a line of a `.spectraignore` file.
+  fn parse(line: &str) -> Option<Self> {
+    let line = line.trim_end();
+
+    if line.is_empty() || line.starts_with('#') {
+      return None;
+    }
+
+    let (negated, line) = match line.strip_prefix('!') {
+      Some(line) => (true, line),
+      None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+
+    let (dir_only, line) = match line.strip_suffix('/') {
+      Some(line) => (true, line),
+      None => (false, line),
+    };
+
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+
+    if line.is_empty() {
+      return None;
+    }
+
+    Some(Self {
+      glob: line.chars().collect(),
+      negated,
+      dir_only,
+      anchored,
+    })
+  }
+
+  fn matches(&self, path: &Path, is_dir: bool) -> bool {
+    if self.dir_only && !is_dir {
+      return false;
+    }
+
+    let text: Vec<char> = if self.anchored {
+      let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+      components.join("/").chars().collect()
+    } else {
+      match path.file_name() {
+        Some(name) => name.to_string_lossy().chars().collect(),
+        None => return false,
+      }
+    };
+
+    glob_match(&self.glob, &text)
+  }
+}
+
+/// Match `text` against a glob.
+fn glob_match(glob: &[char], text: &[char]) -> bool {
+  match glob {
+    [] => text.is_empty(),
+
+    ['*', '*', '/', rest @ ..] => {
+      // zero or more directories
+      glob_match(rest, text)
+        || (0..text.len()).any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+    }
+
+    ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+
+    ['*', rest @ ..] => (0..=text.len())
+      .take_while(|&i| i == 0 || text[i - 1] != '/')
+      .any(|i| glob_match(rest, &text[i..])),
+
+    ['?', rest @ ..] => match text {
+      [c, text @ ..] if *c != '/' => glob_match(rest, text),
+      _ => false,
+    },
+
+    ['[', class @ ..] => match CharClass::parse(class) {
+      Some((char_class, rest)) => match text {
+        [c, text @ ..] if *c != '/' => char_class.contains(*c) && glob_match(rest, text),
+
+        _ => false,
+      },
+
+      // unterminated class; match the bracket literally
+      None => text.first() == Some(&'[') && glob_match(class, &text[1..]),
+    },
+
+    ['\\', c, rest @ ..] | [c, rest @ ..] => {
+      text.first() == Some(c) && glob_match(rest, &text[1..])
+    }
+  }
+}
+
+/// A character class, such as `[a-z]`.
+#[derive(Debug)]
+struct CharClass {
+  negated: bool,
+  ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+  /// Parse a character class (without its opening bracket) and return the rest of the glob.
+  fn parse(class: &[char]) -> Option<(Self, &[char])> {
+    let (negated, mut class) = match class {
+      ['!', class @ ..] | ['^', class @ ..] => (true, class),
+      _ => (false, class),
+    };
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+      match class {
+        [']', rest @ ..] if !first => return Some((Self { negated, ranges }, rest)),
+        [low, '-', high, rest @ ..] if *high != ']' => {
+          ranges.push((*low, *high));
+          class = rest;
+        }
+        [c, rest @ ..] => {
+          ranges.push((*c, *c));
+          class = rest;
+        }
+        [] => return None,
+      }
+
+      first = false;
+    }
+  }
+
+  fn contains(&self, c: char) -> bool {
+    let in_ranges = self.ranges.iter().any(|&(low, high)| low <= c && c <= high);
+    in_ranges != self.negated
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TempDir;
+
+  fn matches(pattern: &str, path: &str, is_dir: bool) -> bool {
+    Pattern::parse(pattern)
+      .unwrap()
+      .matches(Path::new(path), is_dir)
+  }
+
+  #[test]
+  fn parse_patterns() {
+    assert_eq!(Pattern::parse(""), None);
+    assert_eq!(Pattern::parse("# comment"), None);
+    assert_eq!(
+      Pattern::parse("!/build/"),
+      Some(Pattern {
+        glob: "build".chars().collect(),
+        negated: true,
+        dir_only: true,
+        anchored: true,
+      })
+    );
+    assert_eq!(
+      Pattern::parse("\\#not-a-comment"),
+      Some(Pattern {
+        glob: "#not-a-comment".chars().collect(),
+        negated: false,
+        dir_only: false,
+        anchored: false,
+      })
+    );
+  }
+
+  #[test]
+  fn match_patterns() {
+    assert!(matches("*.swp", "foo.obj.swp", false));
+    assert!(matches("*.swp", "shaders/.foo.swp", false));
+    assert!(!matches("*.swp", "foo.obj", false));
+    assert!(matches(".git/", ".git", true));
+    assert!(!matches(".git/", ".git", false));
+    assert!(matches("/scratch", "scratch", true));
+    assert!(!matches("/scratch", "meshes/scratch", true));
+    assert!(matches("meshes/*.blend", "meshes/ship.blend", false));
+    assert!(!matches("meshes/*.blend", "meshes/old/ship.blend", false));
+    assert!(matches("meshes/**/*.blend", "meshes/old/ship.blend", false));
+    assert!(matches("meshes/**/*.blend", "meshes/ship.blend", false));
+    assert!(matches("**/tmp", "a/b/tmp", true));
+    assert!(matches("export/**", "export/a/b.obj", false));
+    assert!(matches("foo?.obj", "foo1.obj", false));
+    assert!(!matches("foo?.obj", "foo.obj", false));
+    assert!(matches("*.[oa]", "lib.a", false));
+    assert!(matches("v[0-9].obj", "v3.obj", false));
+    assert!(!matches("v[!0-9].obj", "v3.obj", false));
+    assert!(matches("*~", "foo.obj~", false));
+  }
+
+  #[test]
+  fn per_directory_rules() {
+    let root = TempDir::new("ignore");
+    fs::create_dir_all(root.join("meshes/wip")).unwrap();
+    fs::write(root.join(IGNORE_FILE_NAME), "*.blend\nwip/\n").unwrap();
+    fs::write(root.join("meshes").join(IGNORE_FILE_NAME), "!keep.blend\n").unwrap();
+
+    let mut rules = IgnoreRules::new(root.to_path_buf());
+
+    assert!(rules.is_ignored(root.join(IGNORE_FILE_NAME), false));
+    assert!(rules.is_ignored(root.join("ship.blend"), false));
+    assert!(!rules.is_ignored(root.join("ship.obj"), false));
+    assert!(rules.is_ignored(root.join("meshes/ship.blend"), false));
+    assert!(!rules.is_ignored(root.join("meshes/keep.blend"), false));
+    assert!(rules.is_ignored(root.join("meshes/wip"), true));
+    assert!(rules.is_ignored(root.join("meshes/wip/keep.blend"), false));
+
+    // rules are cached until invalidated
+    fs::write(root.join(IGNORE_FILE_NAME), "").unwrap();
+    assert!(rules.is_ignored(root.join("ship.blend"), false));
+    rules.invalidate(&root);
+    assert!(!rules.is_ignored(root.join("ship.blend"), false));
+  }
+}
@@ -2,3 +2,84 @@
 
 /// A visual effect.
 pub struct VFX {}
+
+/// A physically-based material, following the metallic-roughness model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+  /// Base color, multiplied with the base color texture if any.
+  pub base_color: [f32; 4],
+
+  /// Base color texture.
+  pub base_color_texture: Option<MaterialTexture>,
+
+  /// Metalness, multiplied with the blue channel of the metallic-roughness texture if any.
+  pub metallic: f32,
+
+  /// Roughness, multiplied with the green channel of the metallic-roughness texture if any.
+  pub roughness: f32,
+
+  /// Metallic-roughness texture.
+  pub metallic_roughness_texture: Option<MaterialTexture>,
+
+  /// Tangent-space normal map.
+  pub normal_texture: Option<MaterialTexture>,
+
+  /// Ambient occlusion texture.
+  pub occlusion_texture: Option<MaterialTexture>,
+
+  /// Emissive color, multiplied with the emissive texture if any.
+  pub emissive: [f32; 3],
+
+  /// Emissive texture.
+  pub emissive_texture: Option<MaterialTexture>,
+
+  /// How the alpha channel of the base color must be interpreted.
+  pub alpha_mode: AlphaMode,
+
+  /// Whether back faces must be rendered too.
+  pub double_sided: bool,
+}
+
+impl Default for Material {
+  fn default() -> Self {
+    Material {
+      base_color: [1., 1., 1., 1.],
+      base_color_texture: None,
+      metallic: 1.,
+      roughness: 1.,
+      metallic_roughness_texture: None,
+      normal_texture: None,
+      occlusion_texture: None,
+      emissive: [0., 0., 0.],
+      emissive_texture: None,
+      alpha_mode: AlphaMode::Opaque,
+      double_sided: false,
+    }
+  }
+}
+
+/// A texture used by a [`Material`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialTexture {
+  /// Image of the texture.
+  ///
+  /// This is the path of the image if it’s stored in its own file, or `<path>#images/<index>` if it’s embedded in the
+  /// file at `<path>`.
+  pub image: String,
+
+  /// Set of texture coordinates used to sample the texture.
+  pub tex_coord: u32,
+}
+
+/// Interpretation of the alpha channel of a [`Material`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+  /// Alpha is ignored.
+  Opaque,
+
+  /// Fragments with an alpha lower than the cutoff are discarded.
+  Mask { cutoff: f32 },
+
+  /// Alpha is used for blending.
+  Blend,
+}
@@ -1,19 +1,33 @@
 //! Mesh related code.
 
 use crate::{
-  entity::{decoder::Decoder, Entity, EntityEvent},
-  system::{resource::ResourceManager, Publisher},
+  entity::{
+    decoder::{Decoded, Decoder, DecodingMetadata},
+    Entity,
+  },
+  system::resource::ResourceManager,
 };
 use colored::Colorize as _;
-use luminance::{tess::Mode, Semantics, Vertex};
+use import::ImportSettings;
+use luminance::{
+  tess::Mode,
+  vertex::{Vertex as _, VertexDesc},
+  Semantics, Vertex,
+};
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   error, fmt, fs,
   path::{Path, PathBuf},
   sync::Arc,
 };
 use wavefront_obj::obj;
 
+pub mod import;
+mod ply;
+pub mod procedural;
+mod stl;
+mod triangulation;
+
 /// Vertex index used in [`Mesh`] to create primitive by connecting vertices.
 pub type MeshIndex = u32;
 
@@ -27,9 +41,24 @@ pub enum VertexSemantics {
   /// Vertex normal.
   #[sem(name = "nor", repr = "[f32; 3]", wrapper = "Nor")]
   Normal,
+
+  /// Vertex texture coordinates.
+  #[sem(name = "uv", repr = "[f32; 2]", wrapper = "UV")]
+  TexCoord,
+
+  /// Vertex tangent; the last component is the handedness of the bitangent.
+  #[sem(name = "tan", repr = "[f32; 4]", wrapper = "Tan")]
+  Tangent,
+
+  /// Vertex color.
+  #[sem(name = "col", repr = "[f32; 4]", wrapper = "Col")]
+  Color,
 }
 
 /// Vertex type used in [`Mesh`].
+///
+/// All the attributes are always present in the vertex; the ones a mesh actually provides are described by its
+/// [`VertexAttributes`]. The others are set to default values.
 #[vertex(sem = "VertexSemantics")]
 #[derive(Clone, Copy, Debug, Vertex)]
 pub struct MeshVertex {
@@ -38,19 +67,230 @@ pub struct MeshVertex {
 
   /// Normal of the vertex.
   nor: Nor,
+
+  /// Texture coordinates of the vertex.
+  uv: UV,
+
+  /// Tangent of the vertex.
+  tan: Tan,
+
+  /// Color of the vertex.
+  col: Col,
+}
+
+impl MeshVertex {
+  /// Default texture coordinates.
+  pub const DEFAULT_UV: [f32; 2] = [0., 0.];
+
+  /// Default tangent.
+  pub const DEFAULT_TANGENT: [f32; 4] = [1., 0., 0., 1.];
+
+  /// Default color.
+  pub const DEFAULT_COLOR: [f32; 4] = [1., 1., 1., 1.];
+
+  /// Create a vertex out of its position and normal.
+  pub fn new(pos: [f32; 3], nor: [f32; 3]) -> Self {
+    MeshVertex {
+      pos: Pos::new(pos),
+      nor: Nor::new(nor),
+      uv: UV::new(Self::DEFAULT_UV),
+      tan: Tan::new(Self::DEFAULT_TANGENT),
+      col: Col::new(Self::DEFAULT_COLOR),
+    }
+  }
+
+  /// Set the texture coordinates of the vertex.
+  pub fn with_uv(self, uv: [f32; 2]) -> Self {
+    MeshVertex {
+      uv: UV::new(uv),
+      ..self
+    }
+  }
+
+  /// Set the tangent of the vertex.
+  pub fn with_tangent(self, tan: [f32; 4]) -> Self {
+    MeshVertex {
+      tan: Tan::new(tan),
+      ..self
+    }
+  }
+
+  /// Set the color of the vertex.
+  pub fn with_color(self, col: [f32; 4]) -> Self {
+    MeshVertex {
+      col: Col::new(col),
+      ..self
+    }
+  }
+
+  pub fn pos(&self) -> [f32; 3] {
+    *self.pos
+  }
+
+  pub fn nor(&self) -> [f32; 3] {
+    *self.nor
+  }
+
+  pub fn uv(&self) -> [f32; 2] {
+    *self.uv
+  }
+
+  pub fn tangent(&self) -> [f32; 4] {
+    *self.tan
+  }
+
+  pub fn color(&self) -> [f32; 4] {
+    *self.col
+  }
+
+  /// Bitwise representation of the vertex, used to find identical vertices.
+  fn key(&self) -> [u32; 16] {
+    let mut key = [0; 16];
+    let components = self
+      .pos()
+      .iter()
+      .chain(&self.nor())
+      .chain(&self.uv())
+      .chain(&self.tangent())
+      .chain(&self.color())
+      .map(|c| c.to_bits())
+      .collect::<Vec<_>>();
+
+    key.copy_from_slice(&components);
+    key
+  }
+}
+
+/// Vertex attributes provided by a [`Mesh`].
+///
+/// Positions and normals are always provided.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct VertexAttributes {
+  /// Whether texture coordinates are provided.
+  pub uv: bool,
+
+  /// Whether tangents are provided.
+  pub tangent: bool,
+
+  /// Whether colors are provided.
+  pub color: bool,
+}
+
+impl VertexAttributes {
+  /// Check whether an attribute, identified by its semantics name, is provided.
+  pub fn contains(&self, sem_name: &str) -> bool {
+    match sem_name {
+      "pos" | "nor" => true,
+      "uv" => self.uv,
+      "tan" => self.tangent,
+      "col" => self.color,
+      _ => false,
+    }
+  }
+}
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AABB {
+  /// Lower corner of the box.
+  pub min: [f32; 3],
+
+  /// Upper corner of the box.
+  pub max: [f32; 3],
+}
+
+impl AABB {
+  /// Smallest box containing all the points; empty sets of points yield a box reduced to the origin.
+  pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+    let mut points = points.into_iter();
+    let first = points.next().unwrap_or([0.; 3]);
+
+    points.fold(
+      AABB {
+        min: first,
+        max: first,
+      },
+      |AABB { min, max }, p| AABB {
+        min: [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+        max: [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+      },
+    )
+  }
+
+  pub fn center(&self) -> [f32; 3] {
+    scale3(add3(self.min, self.max), 0.5)
+  }
+
+  pub fn extent(&self) -> [f32; 3] {
+    sub3(self.max, self.min)
+  }
+}
+
+/// Bounding sphere.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+  pub center: [f32; 3],
+  pub radius: f32,
+}
+
+impl BoundingSphere {
+  /// Sphere centered on the center of `aabb` and containing all the points.
+  pub fn from_points(aabb: &AABB, points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+    let center = aabb.center();
+    let radius = points
+      .into_iter()
+      .map(|p| {
+        let d = sub3(p, center);
+        dot3(d, d)
+      })
+      .fold(0., f32::max)
+      .sqrt();
+
+    BoundingSphere { center, radius }
+  }
 }
 
 /// A mesh.
 ///
-/// A mesh consists of a set of vertices, indices and a primitive mode.
+/// A mesh consists of a set of vertices, indices and a primitive mode. The vertex attributes it provides are described
+/// by [`VertexAttributes`]. Its bounding volumes are computed once, when it’s created.
 #[derive(Debug)]
 pub struct Mesh {
   vertices: Vec<MeshVertex>,
   indices: Vec<MeshIndex>,
   mode: Mode,
+  attributes: VertexAttributes,
+  aabb: AABB,
+  bounding_sphere: BoundingSphere,
 }
 
 impl Mesh {
+  /// Default angle, in degrees, under which adjacent faces are smoothed together when generating normals; see
+  /// [`ImportSettings::smoothing_angle`].
+  pub const DEFAULT_SMOOTHING_ANGLE: f32 = 60.;
+
+  /// Normal of vertices that don’t belong to any surface, such as lines and points.
+  pub const DEFAULT_NORMAL: [f32; 3] = [0., 1., 0.];
+
+  pub fn new(
+    vertices: Vec<MeshVertex>,
+    indices: Vec<MeshIndex>,
+    mode: Mode,
+    attributes: VertexAttributes,
+  ) -> Self {
+    let aabb = AABB::from_points(vertices.iter().map(MeshVertex::pos));
+    let bounding_sphere = BoundingSphere::from_points(&aabb, vertices.iter().map(MeshVertex::pos));
+
+    Mesh {
+      vertices,
+      indices,
+      mode,
+      attributes,
+      aabb,
+      bounding_sphere,
+    }
+  }
+
   pub fn vertices(&self) -> &Vec<MeshVertex> {
     &self.vertices
   }
@@ -63,10 +303,199 @@ impl Mesh {
     self.mode
   }
 
-  fn validate_path(path: &Path) -> Result<(), MeshLoadingError> {
+  pub fn attributes(&self) -> VertexAttributes {
+    self.attributes
+  }
+
+  /// Vertex format of the mesh, restricted to the attributes it provides.
+  pub fn vertex_format(&self) -> VertexDesc {
+    MeshVertex::vertex_desc()
+      .into_iter()
+      .filter(|desc| self.attributes.contains(desc.name))
+      .collect()
+  }
+
+  pub fn aabb(&self) -> &AABB {
+    &self.aabb
+  }
+
+  pub fn bounding_sphere(&self) -> &BoundingSphere {
+    &self.bounding_sphere
+  }
+
+  /// Compute the normals of each corner of a set of triangles.
+  ///
+  /// `triangles` index `positions`. Every face sharing a position with a corner contributes to its normal, weighted by
+  /// its area, if `smooth(face, other_face, cos)` returns `true`, where `cos` is the cosine of the angle between both
+  /// faces. A face always contributes to its own corners, so always returning `false` yields flat normals.
+  pub fn generate_normals(
+    positions: &[[f32; 3]],
+    triangles: &[[usize; 3]],
+    smooth: impl Fn(usize, usize, f32) -> bool,
+  ) -> Vec<[[f32; 3]; 3]> {
+    // area-weighted and unit face normals
+    let face_normals: Vec<_> = triangles
+      .iter()
+      .map(|&[a, b, c]| {
+        cross3(
+          sub3(positions[b], positions[a]),
+          sub3(positions[c], positions[a]),
+        )
+      })
+      .collect();
+    let unit_face_normals: Vec<_> = face_normals.iter().map(|&n| normalize3(n)).collect();
+
+    // faces adjacent to each position
+    let mut adjacency: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (face, triangle) in triangles.iter().enumerate() {
+      for &p in triangle {
+        adjacency.entry(p).or_default().push(face);
+      }
+    }
+
+    triangles
+      .iter()
+      .enumerate()
+      .map(|(face, triangle)| {
+        let mut normals = [[0.; 3]; 3];
+
+        for (corner, p) in triangle.iter().enumerate() {
+          let mut normal = [0.; 3];
+
+          for &other in &adjacency[p] {
+            let cos = dot3(unit_face_normals[face], unit_face_normals[other]);
+
+            if other == face || smooth(face, other, cos) {
+              normal = add3(normal, face_normals[other]);
+            }
+          }
+
+          normals[corner] = if dot3(normal, normal) <= f32::EPSILON {
+            // degenerated faces; pick an arbitrary direction
+            Self::DEFAULT_NORMAL
+          } else {
+            normalize3(normal)
+          };
+        }
+
+        normals
+      })
+      .collect()
+  }
+
+  /// Compute the tangents of a triangle mesh out of its positions, normals and texture coordinates.
+  ///
+  /// Tangents are accumulated per triangle, then orthogonalized against the normal of each vertex.
+  pub fn generate_tangents(vertices: &mut [MeshVertex], indices: &[MeshIndex]) {
+    let mut tangents = vec![[0.; 3]; vertices.len()];
+    let mut bitangents = vec![[0.; 3]; vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+      let [a, b, c] = [
+        triangle[0] as usize,
+        triangle[1] as usize,
+        triangle[2] as usize,
+      ];
+      let (pa, pb, pc) = (vertices[a].pos(), vertices[b].pos(), vertices[c].pos());
+      let (ta, tb, tc) = (vertices[a].uv(), vertices[b].uv(), vertices[c].uv());
+
+      let e1 = sub3(pb, pa);
+      let e2 = sub3(pc, pa);
+      let (du1, dv1) = (tb[0] - ta[0], tb[1] - ta[1]);
+      let (du2, dv2) = (tc[0] - ta[0], tc[1] - ta[1]);
+      let det = du1 * dv2 - du2 * dv1;
+
+      if det.abs() <= f32::EPSILON {
+        // degenerated texture mapping; that triangle doesn’t contribute
+        continue;
+      }
+
+      let r = 1. / det;
+      let t = scale3(sub3(scale3(e1, dv2), scale3(e2, dv1)), r);
+      let bt = scale3(sub3(scale3(e2, du1), scale3(e1, du2)), r);
+
+      for &i in &[a, b, c] {
+        tangents[i] = add3(tangents[i], t);
+        bitangents[i] = add3(bitangents[i], bt);
+      }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+      let n = vertex.nor();
+      let t = sub3(tangents[i], scale3(n, dot3(n, tangents[i])));
+      let len = dot3(t, t).sqrt();
+
+      let tan = if len <= f32::EPSILON {
+        MeshVertex::DEFAULT_TANGENT
+      } else {
+        let t = scale3(t, 1. / len);
+        let w = if dot3(cross3(n, t), bitangents[i]) < 0. {
+          -1.
+        } else {
+          1.
+        };
+        [t[0], t[1], t[2], w]
+      };
+
+      vertex.tan = Tan::new(tan);
+    }
+  }
+
+  /// Build a triangle mesh out of vertices and triangles indexing them.
+  ///
+  /// Identical vertices are merged. If the vertices don’t have normals, a `smoothing_angle` must be given to generate
+  /// them. Tangents are generated if texture coordinates are provided but tangents are not.
+  fn from_triangles(
+    vertices: &[MeshVertex],
+    triangles: &[[usize; 3]],
+    smoothing_angle: Option<f32>,
+    mut attributes: VertexAttributes,
+  ) -> Self {
+    let has_normals = smoothing_angle.is_none();
+    let generated_normals = match smoothing_angle {
+      None => Vec::new(),
+
+      Some(smoothing_angle) => {
+        let positions: Vec<_> = vertices.iter().map(MeshVertex::pos).collect();
+        let smoothing_cos = smoothing_angle.to_radians().cos();
+
+        Self::generate_normals(&positions, triangles, |_, _, cos| cos >= smoothing_cos)
+      }
+    };
+
+    let mut vertex_cache: HashMap<[u32; 16], MeshIndex> = HashMap::new();
+    let mut mesh_vertices = Vec::new();
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+
+    for (triangle_index, triangle) in triangles.iter().enumerate() {
+      for (corner, &index) in triangle.iter().enumerate() {
+        let mut vertex = vertices[index];
+
+        if !has_normals {
+          vertex.nor = Nor::new(generated_normals[triangle_index][corner]);
+        }
+
+        let vertex_index = *vertex_cache.entry(vertex.key()).or_insert_with(|| {
+          mesh_vertices.push(vertex);
+          (mesh_vertices.len() - 1) as MeshIndex
+        });
+
+        indices.push(vertex_index);
+      }
+    }
+
+    if attributes.uv && !attributes.tangent {
+      Self::generate_tangents(&mut mesh_vertices, &indices);
+      attributes.tangent = true;
+    }
+
+    Mesh::new(mesh_vertices, indices, Mode::Triangle, attributes)
+  }
+
+  fn validate_path(path: &Path, format: &str) -> Result<(), MeshLoadingError> {
     log::info!(
       "loading {} at {}",
-      "obj".yellow().italic(),
+      format.yellow().italic(),
       path.display().to_string().purple().italic()
     );
 
@@ -77,8 +506,12 @@ impl Mesh {
     }
   }
 
-  pub fn load_from_path(path: &Path) -> Result<Self, MeshLoadingError> {
-    Self::validate_path(path)?;
+  /// Load all the meshes contained in an OBJ file.
+  ///
+  /// Every object and every geometry group of the file yields its own [`Mesh`], returned along with its
+  /// `object/group` name. The [`ImportSettings`] of the file, if any, are applied to all of them.
+  pub fn load_from_path(path: &Path) -> Result<Vec<(String, Self)>, MeshLoadingError> {
+    Self::validate_path(path, "obj")?;
 
     // read the content of the path at once (no streaming)
     log::debug!(
@@ -88,80 +521,396 @@ impl Mesh {
     let file_content = fs::read_to_string(path)
       .map_err(|e| MeshLoadingError::cannot_read_path(path, e.to_string()))?;
 
-    let obj_set =
-      obj::parse(file_content).map_err(|e| MeshLoadingError::cannot_parse(path, e.to_string()))?;
+    // the parser fan-triangulates polygons, which doesn’t work with concave ones, so we triangulate them first
+    let obj_set = obj::parse(triangulation::triangulate_obj(&file_content))
+      .map_err(|e| MeshLoadingError::cannot_parse(path, e.to_string()))?;
 
-    Self::traverse_obj_set(obj_set)
-  }
+    let settings = ImportSettings::load(path)?;
+    let (names, meshes): (Vec<_>, Vec<_>) =
+      Self::traverse_obj_set(obj_set, Self::smoothing_angle(&settings))
+        .into_iter()
+        .unzip();
 
-  fn traverse_obj_set(obj_set: obj::ObjSet) -> Result<Self, MeshLoadingError> {
-    let objects = obj_set.objects;
-    if objects.len() != 1 {
-      return Err(MeshLoadingError::too_many_objects(objects.len()));
+    if meshes.is_empty() {
+      return Err(MeshLoadingError::no_mesh(path));
     }
 
-    let object = objects.into_iter().next().unwrap();
-    if object.geometry.len() != 1 {
-      return Err(MeshLoadingError::too_many_geometries(object.geometry.len()));
+    let meshes = Self::apply_import_settings(path, settings, meshes);
+    Ok(names.into_iter().zip(meshes).collect())
+  }
+
+  /// Load a mesh from a PLY file, either ASCII or binary, and apply its [`ImportSettings`], if any.
+  pub fn load_from_ply_path(path: &Path) -> Result<Self, MeshLoadingError> {
+    Self::validate_path(path, "ply")?;
+
+    let bytes =
+      fs::read(path).map_err(|e| MeshLoadingError::cannot_read_path(path, e.to_string()))?;
+    let settings = ImportSettings::load(path)?;
+    let mesh = ply::parse(&bytes, Self::smoothing_angle(&settings))
+      .map_err(|reason| MeshLoadingError::cannot_parse(path, reason))?;
+
+    Ok(Self::apply_import_settings(path, settings, vec![mesh]).remove(0))
+  }
+
+  /// Load a procedural mesh from its JSON description.
+  pub fn load_from_description_path(path: &Path) -> Result<Self, MeshLoadingError> {
+    Self::validate_path(path, "mesh description")?;
+
+    let content = fs::read_to_string(path)
+      .map_err(|e| MeshLoadingError::cannot_read_path(path, e.to_string()))?;
+    let description: procedural::MeshDescription = serde_json::from_str(&content)
+      .map_err(|e| MeshLoadingError::cannot_parse(path, e.to_string()))?;
+
+    description
+      .build()
+      .map_err(|reason| MeshLoadingError::cannot_parse(path, reason))
+  }
+
+  /// Load a mesh from a STL file, either ASCII or binary, and apply its [`ImportSettings`], if any.
+  pub fn load_from_stl_path(path: &Path) -> Result<Self, MeshLoadingError> {
+    Self::validate_path(path, "stl")?;
+
+    let bytes =
+      fs::read(path).map_err(|e| MeshLoadingError::cannot_read_path(path, e.to_string()))?;
+    let settings = ImportSettings::load(path)?;
+    let mesh = stl::parse(&bytes).map_err(|reason| MeshLoadingError::cannot_parse(path, reason))?;
+
+    Ok(Self::apply_import_settings(path, settings, vec![mesh]).remove(0))
+  }
+
+  /// Smoothing angle used to generate the normals of meshes loaded from a file with the given [`ImportSettings`].
+  fn smoothing_angle(settings: &Option<ImportSettings>) -> f32 {
+    settings
+      .as_ref()
+      .map_or(Self::DEFAULT_SMOOTHING_ANGLE, |settings| {
+        settings.smoothing_angle
+      })
+  }
+
+  /// Apply the [`ImportSettings`] of a mesh file, if any, to the meshes loaded from it.
+  fn apply_import_settings(
+    path: &Path,
+    settings: Option<ImportSettings>,
+    meshes: Vec<Self>,
+  ) -> Vec<Self> {
+    match settings {
+      Some(settings) => {
+        log::debug!(
+          "  applying import settings to {}",
+          path.display().to_string().purple().italic()
+        );
+
+        settings.apply(meshes)
+      }
+
+      None => meshes,
     }
+  }
 
-    Self::traverse_object(object)
+  /// Decoding metadata of meshes loaded from a mesh file, which depend on its [`ImportSettings`].
+  ///
+  /// The import settings are a dependency even if they don’t exist yet, so that creating them reloads the meshes.
+  fn decoding_metadata(path: &Path) -> DecodingMetadata {
+    let mut metadata = DecodingMetadata::from_source(path);
+    let _ = metadata.add_dep(ImportSettings::sidecar_path(path));
+    metadata
   }
 
-  fn traverse_object(object: obj::Object) -> Result<Self, MeshLoadingError> {
-    let geometry = object.geometry.into_iter().next().unwrap();
+  fn traverse_obj_set(obj_set: obj::ObjSet, smoothing_angle: f32) -> Vec<(String, Self)> {
+    let mut meshes = Vec::new();
 
+    for (object_index, object) in obj_set.objects.into_iter().enumerate() {
+      // unnamed objects are named after their index in the file
+      let object_name = if object.name.is_empty() {
+        object_index.to_string()
+      } else {
+        object.name.clone()
+      };
+
+      meshes.extend(
+        Self::traverse_object(object, smoothing_angle)
+          .into_iter()
+          .map(|(geometry_name, mesh)| (format!("{}/{}", object_name, geometry_name), mesh)),
+      );
+    }
+
+    meshes
+  }
+
+  fn traverse_object(object: obj::Object, smoothing_angle: f32) -> Vec<(String, Self)> {
     log::debug!("  loading object {}", object.name);
     log::debug!("    {} vertices", object.vertices.len());
-    log::debug!("    {} shapes", geometry.shapes.len());
+    log::debug!("    {} geometries", object.geometry.len());
+
+    let mut names = HashSet::new();
+    let mut meshes = Vec::with_capacity(object.geometry.len());
+
+    // geometries only change with materials, so split them by group, so that every group gets its own meshes
+    let groups = object
+      .geometry
+      .into_iter()
+      .flat_map(Self::split_geometry_by_group);
+
+    for (group_index, (group, geometry)) in groups.enumerate() {
+      log::debug!(
+        "    {} shapes in group {}",
+        geometry.shapes.len(),
+        group_index
+      );
+
+      // name geometries after their group; fall back to their index if they have none or if the name is ambiguous
+      let name = group
+        .filter(|name| !names.contains(name))
+        .unwrap_or_else(|| group_index.to_string());
+      names.insert(name.clone());
+
+      let geometry_meshes = Self::traverse_geometry(
+        &object.vertices,
+        &object.tex_vertices,
+        &object.normals,
+        geometry,
+        smoothing_angle,
+      );
+
+      // if a geometry mixes faces, lines and points, the first mesh keeps the name of the geometry and the next ones
+      // are suffixed by their primitive
+      for (i, mesh) in geometry_meshes.into_iter().enumerate() {
+        let name = match mesh.mode {
+          Mode::Line if i > 0 => format!("{}.lines", name),
+          Mode::Point if i > 0 => format!("{}.points", name),
+          _ => name.clone(),
+        };
+
+        meshes.push((name, mesh));
+      }
+    }
+
+    meshes
+  }
+
+  /// Split the shapes of a geometry by their first group, in order of appearance.
+  fn split_geometry_by_group(geometry: obj::Geometry) -> Vec<(Option<String>, obj::Geometry)> {
+    let mut groups: Vec<(Option<String>, obj::Geometry)> = Vec::new();
+
+    for shape in geometry.shapes {
+      let group = shape.groups.first().cloned();
 
-    Self::traverse_geometry(object.vertices, object.normals, geometry)
+      match groups.iter_mut().find(|(name, _)| *name == group) {
+        Some((_, group_geometry)) => group_geometry.shapes.push(shape),
+
+        None => groups.push((
+          group,
+          obj::Geometry {
+            material_name: geometry.material_name.clone(),
+            shapes: vec![shape],
+          },
+        )),
+      }
+    }
+
+    groups
   }
 
+  /// Build the meshes of a geometry; one per kind of primitive (faces, lines and points), in that order.
   fn traverse_geometry(
-    obj_vertices: Vec<obj::Vertex>,
-    obj_normals: Vec<obj::Normal>,
+    obj_vertices: &[obj::Vertex],
+    obj_tex_vertices: &[obj::TVertex],
+    obj_normals: &[obj::Normal],
     geometry: obj::Geometry,
-  ) -> Result<Self, MeshLoadingError> {
-    // build up vertices; for this to work, we remove duplicated vertices by putting them in a
-    // map associating the vertex with its ID
-    let mut vertex_cache: HashMap<obj::VTNIndex, MeshIndex> = HashMap::new();
-    let mut vertices: Vec<MeshVertex> = Vec::new();
-    let mut indices: Vec<MeshIndex> = Vec::new();
+    smoothing_angle: f32,
+  ) -> Vec<Self> {
+    let mut triangles = Vec::new();
+    let mut smoothing_groups = Vec::new();
+    let mut lines = Vec::new();
+    let mut points = Vec::new();
 
     for shape in geometry.shapes {
-      if let obj::Primitive::Triangle(a, b, c) = shape.primitive {
-        for key in &[a, b, c] {
-          if let Some(vertex_index) = vertex_cache.get(key) {
-            indices.push(*vertex_index);
+      match shape.primitive {
+        obj::Primitive::Triangle(a, b, c) => {
+          triangles.push([a, b, c]);
+          smoothing_groups.push(shape.smoothing_groups);
+        }
+
+        obj::Primitive::Line(a, b) => lines.extend_from_slice(&[a, b]),
+        obj::Primitive::Point(a) => points.push(a),
+      }
+    }
+
+    let mut meshes = Vec::new();
+    let mut builder = OBJMeshBuilder::new(obj_vertices, obj_tex_vertices, obj_normals);
+
+    if !triangles.is_empty() {
+      // generate normals for the vertices that don’t have any; if the geometry uses smoothing groups, faces are
+      // smoothed together only if they share one of them; otherwise, the smoothing angle decides
+      let generated_normals = if triangles.iter().flatten().any(|key| key.2.is_none()) {
+        let positions: Vec<_> = obj_vertices
+          .iter()
+          .map(|p| [p.x as f32, p.y as f32, p.z as f32])
+          .collect();
+        let position_triangles: Vec<_> =
+          triangles.iter().map(|[a, b, c]| [a.0, b.0, c.0]).collect();
+        let uses_smoothing_groups = smoothing_groups.iter().any(|groups| !groups.is_empty());
+        let smoothing_cos = smoothing_angle.to_radians().cos();
+
+        Self::generate_normals(&positions, &position_triangles, |a, b, cos| {
+          if uses_smoothing_groups {
+            smoothing_groups[a]
+              .iter()
+              .any(|group| *group != 0 && smoothing_groups[b].contains(group))
           } else {
-            let p = obj_vertices[key.0];
-            let n = obj_normals[key.2.ok_or_else(MeshLoadingError::missing_vertex_normal)?];
-            let pos = Pos::new([p.x as f32, p.y as f32, p.z as f32]);
-            let nor = Nor::new([n.x as f32, n.y as f32, n.z as f32]);
-            let vertex = MeshVertex { pos, nor };
-            let vertex_index = vertices.len() as MeshIndex;
-
-            vertex_cache.insert(*key, vertex_index);
-            vertices.push(vertex);
-            indices.push(vertex_index);
+            cos >= smoothing_cos
           }
-        }
+        })
       } else {
-        return Err(MeshLoadingError::unsupported_primitive_mode());
+        Vec::new()
+      };
+
+      for (triangle_index, triangle) in triangles.iter().enumerate() {
+        for (corner, key) in triangle.iter().enumerate() {
+          builder.push(*key, || generated_normals[triangle_index][corner]);
+        }
+      }
+
+      let mut mesh = builder.build(Mode::Triangle);
+
+      // OBJ doesn’t carry tangents, so we compute them whenever we can
+      if mesh.attributes.uv {
+        Self::generate_tangents(&mut mesh.vertices, &mesh.indices);
+        mesh.attributes.tangent = true;
       }
+
+      meshes.push(mesh);
+      builder = OBJMeshBuilder::new(obj_vertices, obj_tex_vertices, obj_normals);
     }
 
-    let mode = Mode::Triangle;
+    // lines and points have no surface to compute normals from
+    if !lines.is_empty() {
+      for key in lines {
+        builder.push(key, || Self::DEFAULT_NORMAL);
+      }
 
-    Ok(Mesh {
-      vertices,
-      indices,
-      mode,
-    })
+      meshes.push(builder.build(Mode::Line));
+      builder = OBJMeshBuilder::new(obj_vertices, obj_tex_vertices, obj_normals);
+    }
+
+    if !points.is_empty() {
+      for key in points {
+        builder.push(key, || Self::DEFAULT_NORMAL);
+      }
+
+      meshes.push(builder.build(Mode::Point));
+    }
+
+    meshes
+  }
+}
+
+/// Build a [`Mesh`] out of OBJ vertices, deduplicating them.
+struct OBJMeshBuilder<'a> {
+  obj_vertices: &'a [obj::Vertex],
+  obj_tex_vertices: &'a [obj::TVertex],
+  obj_normals: &'a [obj::Normal],
+  // generated normals are part of the key, as the same OBJ vertex can be shared by flat faces
+  vertex_cache: HashMap<(obj::VTNIndex, Option<[u32; 3]>), MeshIndex>,
+  vertices: Vec<MeshVertex>,
+  indices: Vec<MeshIndex>,
+  attributes: VertexAttributes,
+}
+
+impl<'a> OBJMeshBuilder<'a> {
+  fn new(
+    obj_vertices: &'a [obj::Vertex],
+    obj_tex_vertices: &'a [obj::TVertex],
+    obj_normals: &'a [obj::Normal],
+  ) -> Self {
+    OBJMeshBuilder {
+      obj_vertices,
+      obj_tex_vertices,
+      obj_normals,
+      vertex_cache: HashMap::new(),
+      vertices: Vec::new(),
+      indices: Vec::new(),
+      attributes: VertexAttributes::default(),
+    }
+  }
+
+  /// Push a vertex; `generate_normal` is used if the vertex doesn’t have a normal.
+  fn push(&mut self, key: obj::VTNIndex, generate_normal: impl FnOnce() -> [f32; 3]) {
+    let nor = match key.2 {
+      Some(n) => {
+        let n = self.obj_normals[n];
+        [n.x as f32, n.y as f32, n.z as f32]
+      }
+
+      None => generate_normal(),
+    };
+
+    let generated_key = if key.2.is_none() {
+      Some([nor[0].to_bits(), nor[1].to_bits(), nor[2].to_bits()])
+    } else {
+      None
+    };
+    let cache_key = (key, generated_key);
+
+    if let Some(vertex_index) = self.vertex_cache.get(&cache_key) {
+      self.indices.push(*vertex_index);
+    } else {
+      let p = self.obj_vertices[key.0];
+      let mut vertex = MeshVertex::new([p.x as f32, p.y as f32, p.z as f32], nor);
+
+      if let Some(t) = key.1 {
+        let t = self.obj_tex_vertices[t];
+        vertex = vertex.with_uv([t.u as f32, t.v as f32]);
+        self.attributes.uv = true;
+      }
+
+      let vertex_index = self.vertices.len() as MeshIndex;
+
+      self.vertex_cache.insert(cache_key, vertex_index);
+      self.vertices.push(vertex);
+      self.indices.push(vertex_index);
+    }
+  }
+
+  fn build(self, mode: Mode) -> Mesh {
+    Mesh::new(self.vertices, self.indices, mode, self.attributes)
+  }
+}
+
+fn add3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale3(a: [f32; 3], k: f32) -> [f32; 3] {
+  [a[0] * k, a[1] * k, a[2] * k]
+}
+
+fn normalize3(a: [f32; 3]) -> [f32; 3] {
+  let len = dot3(a, a).sqrt();
+
+  if len <= f32::EPSILON {
+    a
+  } else {
+    scale3(a, 1. / len)
   }
 }
 
+fn dot3(a: [f32; 3], b: [f32; 3]) -> f32 {
+  a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross3(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [
+    a[1] * b[2] - a[2] * b[1],
+    a[2] * b[0] - a[0] * b[2],
+    a[0] * b[1] - a[1] * b[0],
+  ]
+}
+
 /// Possible errors that can happen while loading a [`Mesh`].
 #[derive(Debug)]
 #[non_exhaustive]
@@ -175,20 +924,8 @@ pub enum MeshLoadingError {
   /// Parsing failure while loading a mesh.
   CannotParse { path: PathBuf, reason: String },
 
-  // TODO: this should be supported and generates several Mesh
-  // TODO: add the path of the mesh being loaded
-  /// Too many objects detected while loading the mesh.
-  TooManyObjects(usize),
-
-  /// Too many geometries detected while loading the mesh.
-  // TODO: add the path of the mesh being loaded and the object name
-  TooManyGeometries(usize),
-
-  /// Insupported primitive mode.
-  UnsupportedPrimitiveMode,
-
-  /// A vertex is missing a normal.
-  MissingVertexNormal,
+  /// No mesh was found in the file.
+  NoMesh(PathBuf),
 }
 
 impl MeshLoadingError {
@@ -206,20 +943,8 @@ impl MeshLoadingError {
     }
   }
 
-  fn too_many_objects(nb: usize) -> Self {
-    MeshLoadingError::TooManyObjects(nb)
-  }
-
-  fn too_many_geometries(nb: usize) -> Self {
-    MeshLoadingError::TooManyGeometries(nb)
-  }
-
-  fn unsupported_primitive_mode() -> Self {
-    MeshLoadingError::UnsupportedPrimitiveMode
-  }
-
-  fn missing_vertex_normal() -> Self {
-    MeshLoadingError::MissingVertexNormal
+  fn no_mesh(path: impl Into<PathBuf>) -> Self {
+    MeshLoadingError::NoMesh(path.into())
   }
 }
 
@@ -244,13 +969,7 @@ impl fmt::Display for MeshLoadingError {
         ref reason,
       } => write!(f, "cannot parse {}: {}", path.display(), reason),
 
-      MeshLoadingError::TooManyObjects(nb) => write!(f, "too many objects; {}", nb),
-
-      MeshLoadingError::TooManyGeometries(nb) => write!(f, "too many geometries; {}", nb),
-
-      MeshLoadingError::UnsupportedPrimitiveMode => write!(f, "unsupported primitive mode"),
-
-      MeshLoadingError::MissingVertexNormal => write!(f, "a vertex is missing its normal"),
+      MeshLoadingError::NoMesh(ref path) => write!(f, "no mesh found in {}", path.display()),
     }
   }
 }
@@ -269,32 +988,273 @@ impl Decoder for OBJDecoder {
   type Err = MeshLoadingError;
 
   fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
-    publisher: &mut impl Publisher<EntityEvent>,
+    _: &ResourceManager<Entity>,
     path: impl AsRef<Path>,
-  ) -> Result<(), Self::Err> {
+  ) -> Result<Vec<Decoded>, Self::Err> {
     let path = path.as_ref();
+    let path_name = path.display().to_string();
+    let decoded = Mesh::load_from_path(path)?
+      .into_iter()
+      .map(|(name, mesh)| {
+        Decoded::new(
+          format!("{}#{}", path_name, name),
+          Entity::Mesh(Arc::new(mesh)),
+          Mesh::decoding_metadata(path),
+        )
+      })
+      .collect();
 
-    match Mesh::load_from_path(path) {
-      Ok(mesh) => {
-        let path_name = path.display().to_string();
-        let path = path_name.purple().italic();
-        let mesh = Entity::Mesh(Arc::new(mesh));
-        let handle = resources.wrap(mesh.clone(), path_name, None);
+    Ok(decoded)
+  }
+}
 
-        log::debug!("assigned {} handle {}", path, handle);
-        log::info!("{} mesh {} at {}", "loaded".green().bold(), handle, path);
+/// The PLY encoding format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PLYDecoder;
 
-        let event = EntityEvent::Loaded {
-          handle,
-          entity: mesh,
-        };
-        publisher.publish(event);
+impl Decoder for PLYDecoder {
+  const EXT: &'static str = "ply";
 
-        Ok(())
-      }
+  const SUB_EXT: &'static str = "";
+
+  type Err = MeshLoadingError;
 
-      Err(err) => Err(err),
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    let path = path.as_ref();
+    let mesh = Mesh::load_from_ply_path(path)?;
+
+    Ok(vec![Decoded::new(
+      path.display().to_string(),
+      Entity::Mesh(Arc::new(mesh)),
+      Mesh::decoding_metadata(path),
+    )])
+  }
+}
+
+/// The STL encoding format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct STLDecoder;
+
+impl Decoder for STLDecoder {
+  const EXT: &'static str = "stl";
+
+  const SUB_EXT: &'static str = "";
+
+  type Err = MeshLoadingError;
+
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    let path = path.as_ref();
+    let mesh = Mesh::load_from_stl_path(path)?;
+
+    Ok(vec![Decoded::new(
+      path.display().to_string(),
+      Entity::Mesh(Arc::new(mesh)),
+      Mesh::decoding_metadata(path),
+    )])
+  }
+}
+
+/// The procedural mesh JSON description format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ProceduralMeshDecoder;
+
+impl Decoder for ProceduralMeshDecoder {
+  const EXT: &'static str = "json";
+
+  const SUB_EXT: &'static str = "mesh";
+
+  type Err = MeshLoadingError;
+
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    let path = path.as_ref();
+    let mesh = Mesh::load_from_description_path(path)?;
+
+    Ok(vec![Decoded::new(
+      path.display().to_string(),
+      Entity::Mesh(Arc::new(mesh)),
+      DecodingMetadata::from_source(path),
+    )])
+  }
+}
+
+/// The mesh import settings format.
+///
+/// Import settings are applied by the decoders of the mesh files they accompany; decoding them on their own only checks
+/// that they’re valid and yields no entity.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ImportSettingsDecoder;
+
+impl Decoder for ImportSettingsDecoder {
+  const EXT: &'static str = "json";
+
+  const SUB_EXT: &'static str = "import";
+
+  type Err = MeshLoadingError;
+
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    ImportSettings::load_from_sidecar_path(path.as_ref())?;
+    Ok(Vec::new())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tangents_follow_uv() {
+    // a quad in the XY plane facing +Z, with U along +X and V along +Y
+    let mut vertices = vec![
+      MeshVertex::new([0., 0., 0.], [0., 0., 1.]).with_uv([0., 0.]),
+      MeshVertex::new([1., 0., 0.], [0., 0., 1.]).with_uv([1., 0.]),
+      MeshVertex::new([1., 1., 0.], [0., 0., 1.]).with_uv([1., 1.]),
+      MeshVertex::new([0., 1., 0.], [0., 0., 1.]).with_uv([0., 1.]),
+    ];
+    let indices = [0, 1, 2, 0, 2, 3];
+
+    Mesh::generate_tangents(&mut vertices, &indices);
+
+    for vertex in &vertices {
+      assert_eq!(vertex.tangent(), [1., 0., 0., 1.]);
+    }
+
+    // mirroring V flips the handedness
+    for vertex in &mut vertices {
+      let [u, v] = vertex.uv();
+      *vertex = vertex.with_uv([u, 1. - v]);
+    }
+
+    Mesh::generate_tangents(&mut vertices, &indices);
+
+    for vertex in &vertices {
+      assert_eq!(vertex.tangent(), [1., 0., 0., -1.]);
+    }
+  }
+
+  #[test]
+  fn vertex_format_lists_provided_attributes() {
+    let attributes = VertexAttributes {
+      uv: true,
+      color: true,
+      ..VertexAttributes::default()
+    };
+    let vertices = vec![MeshVertex::new([0., 0., 0.], Mesh::DEFAULT_NORMAL)];
+    let mesh = Mesh::new(vertices, vec![0], Mode::Point, attributes);
+    let names: Vec<_> = mesh
+      .vertex_format()
+      .into_iter()
+      .map(|desc| desc.name)
+      .collect();
+
+    assert_eq!(names, ["pos", "nor", "uv", "col"]);
+  }
+
+  const CUBE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n";
+  const CUBE_FACES: &str = "f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+  fn load_obj(src: &str) -> Vec<(String, Mesh)> {
+    Mesh::traverse_obj_set(
+      obj::parse(triangulation::triangulate_obj(src)).unwrap(),
+      Mesh::DEFAULT_SMOOTHING_ANGLE,
+    )
+  }
+
+  #[test]
+  fn generated_normals_are_flat_above_smoothing_angle() {
+    let meshes = load_obj(&format!("{}{}", CUBE, CUBE_FACES));
+    let mesh = &meshes[0].1;
+
+    // every face of the cube has its own four vertices
+    assert_eq!(mesh.vertices().len(), 24);
+
+    for vertex in mesh.vertices() {
+      let n = vertex.nor();
+      assert_eq!(n.iter().filter(|c| c.abs() == 1.).count(), 1);
     }
   }
+
+  #[test]
+  fn generated_normals_follow_smoothing_angle() {
+    let obj_set = obj::parse(triangulation::triangulate_obj(&format!(
+      "{}{}",
+      CUBE, CUBE_FACES
+    )))
+    .unwrap();
+    let meshes = Mesh::traverse_obj_set(obj_set, 100.);
+
+    // faces of the cube are 90° apart, so they’re all smoothed together
+    assert_eq!(meshes[0].1.vertices().len(), 8);
+  }
+
+  #[test]
+  fn generated_normals_follow_smoothing_groups() {
+    let meshes = load_obj(&format!("{}s 1\n{}", CUBE, CUBE_FACES));
+    let mesh = &meshes[0].1;
+
+    // all faces are smoothed together, so corners are shared
+    assert_eq!(mesh.vertices().len(), 8);
+
+    for vertex in mesh.vertices() {
+      // normals point away from the center of the cube, along its diagonals
+      let (p, n) = (vertex.pos(), vertex.nor());
+      assert!((0..3).all(|i| (p[i] - 0.5) * n[i] > 0.));
+    }
+  }
+
+  #[test]
+  fn lines_and_points() {
+    let meshes = load_obj(&format!("{}{}l 1 2 3\np 7 8\n", CUBE, CUBE_FACES));
+    let modes: Vec<_> = meshes
+      .iter()
+      .map(|(name, mesh)| (name.as_str(), mesh.mode(), mesh.indices().len()))
+      .collect();
+
+    assert_eq!(
+      modes,
+      vec![
+        ("0/0", Mode::Triangle, 36),
+        ("0/0.lines", Mode::Line, 4),
+        ("0/0.points", Mode::Point, 2)
+      ]
+    );
+  }
+
+  #[test]
+  fn geometries_are_named_after_their_group() {
+    // both groups share a material, and the left one comes back with another material
+    let faces =
+      "usemtl stone\ng left\nf 1 4 3 2\ng right\nf 5 6 7 8\nusemtl wood\ng left\nf 1 2 6 5\n";
+    let meshes = load_obj(&format!("o cube\n{}{}", CUBE, faces));
+    let names: Vec<_> = meshes.iter().map(|(name, _)| name.as_str()).collect();
+
+    assert_eq!(names, vec!["cube/left", "cube/right", "cube/2"]);
+  }
+
+  #[test]
+  fn bounding_volumes() {
+    let meshes = load_obj(&format!("{}{}", CUBE, CUBE_FACES));
+    let mesh = &meshes[0].1;
+
+    assert_eq!(
+      *mesh.aabb(),
+      AABB {
+        min: [0., 0., 0.],
+        max: [1., 1., 1.]
+      }
+    );
+    assert_eq!(mesh.bounding_sphere().center, [0.5, 0.5, 0.5]);
+    assert!((mesh.bounding_sphere().radius - 0.75f32.sqrt()).abs() < 1e-6);
+  }
 }
@@ -0,0 +1,347 @@
+//! Import-time mesh transforms.
+//!
+//! Meshes exported by modeling tools often come in with the wrong scale, Z-up axes or an off-center pivot. Rather than
+//! fixing the exported files, a mesh file such as `foo.obj` can be accompanied by a `foo.obj.import.json` sidecar
+//! describing how to correct it when it’s loaded:
+//!
+//! ```json
+//! { "scale": 0.01, "axes": ["x", "z", "-y"], "recenter": "bottom", "flip-winding": false, "flip-normals": false,
+//!   "smoothing-angle": 45 }
+//! ```
+//!
+//! Missing settings take their default value, which leaves the mesh untouched. Transforms are applied in the following
+//! order: axis swap, scale, recentering, then winding and normal flips. The smoothing angle is used while the file is
+//! parsed, to generate the normals it lacks.
+
+use super::{Mesh, MeshLoadingError, MeshVertex, AABB};
+use luminance::tess::Mode;
+use serde::{Deserialize, Serialize};
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// Suffix appended to the path of a mesh file to get the path of its import settings.
+pub const IMPORT_SETTINGS_SUFFIX: &str = ".import.json";
+
+/// Source axis, possibly negated, a destination axis is taken from.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Axis {
+  #[serde(rename = "x")]
+  X,
+  #[serde(rename = "-x")]
+  NegX,
+  #[serde(rename = "y")]
+  Y,
+  #[serde(rename = "-y")]
+  NegY,
+  #[serde(rename = "z")]
+  Z,
+  #[serde(rename = "-z")]
+  NegZ,
+}
+
+impl Axis {
+  /// Index of the source component.
+  fn index(self) -> usize {
+    match self {
+      Axis::X | Axis::NegX => 0,
+      Axis::Y | Axis::NegY => 1,
+      Axis::Z | Axis::NegZ => 2,
+    }
+  }
+
+  fn sign(self) -> f32 {
+    match self {
+      Axis::X | Axis::Y | Axis::Z => 1.,
+      Axis::NegX | Axis::NegY | Axis::NegZ => -1.,
+    }
+  }
+}
+
+/// Where the origin of a mesh is moved to.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Recenter {
+  /// Keep the origin of the file.
+  None,
+
+  /// Move the origin to the center of the bounding box.
+  Center,
+
+  /// Move the origin to the center of the bottom face of the bounding box.
+  Bottom,
+}
+
+/// Transforms applied to meshes when they’re loaded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ImportSettings {
+  /// Uniform scale factor.
+  pub scale: f32,
+
+  /// Source axis of the X, Y and Z axes; `["x", "z", "-y"]` converts Z-up meshes to Y-up.
+  pub axes: [Axis; 3],
+
+  /// Where the origin is moved to, after swapping axes and scaling.
+  pub recenter: Recenter,
+
+  /// Reverse the winding order of triangles.
+  pub flip_winding: bool,
+
+  /// Reverse normals.
+  pub flip_normals: bool,
+
+  /// Angle, in degrees, under which adjacent faces are smoothed together when normals are generated.
+  pub smoothing_angle: f32,
+}
+
+impl Default for ImportSettings {
+  fn default() -> Self {
+    ImportSettings {
+      scale: 1.,
+      axes: [Axis::X, Axis::Y, Axis::Z],
+      recenter: Recenter::None,
+      flip_winding: false,
+      flip_normals: false,
+      smoothing_angle: Mesh::DEFAULT_SMOOTHING_ANGLE,
+    }
+  }
+}
+
+impl ImportSettings {
+  /// Path of the import settings of a mesh file.
+  pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(IMPORT_SETTINGS_SUFFIX);
+    sidecar.into()
+  }
+
+  /// Load the import settings of a mesh file, if it has any.
+  pub fn load(path: &Path) -> Result<Option<Self>, MeshLoadingError> {
+    let sidecar = Self::sidecar_path(path);
+
+    if sidecar.is_file() {
+      Self::load_from_sidecar_path(&sidecar).map(Some)
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Load import settings from their sidecar file.
+  pub fn load_from_sidecar_path(sidecar: &Path) -> Result<Self, MeshLoadingError> {
+    let content = fs::read_to_string(sidecar)
+      .map_err(|e| MeshLoadingError::cannot_read_path(sidecar, e.to_string()))?;
+    let settings: Self = serde_json::from_str(&content)
+      .map_err(|e| MeshLoadingError::cannot_parse(sidecar, e.to_string()))?;
+
+    settings
+      .validate()
+      .map_err(|reason| MeshLoadingError::cannot_parse(sidecar, reason))?;
+
+    Ok(settings)
+  }
+
+  fn validate(&self) -> Result<(), String> {
+    if !self.scale.is_finite() || self.scale <= 0. {
+      return Err(format!("scale must be positive, got {}", self.scale));
+    }
+
+    if !(0. ..=180.).contains(&self.smoothing_angle) {
+      return Err(format!(
+        "smoothing angle must be between 0 and 180 degrees, got {}",
+        self.smoothing_angle
+      ));
+    }
+
+    let mut used = [false; 3];
+    for axis in &self.axes {
+      if used[axis.index()] {
+        return Err(format!("axes {:?} don’t form a permutation", self.axes));
+      }
+
+      used[axis.index()] = true;
+    }
+
+    Ok(())
+  }
+
+  /// Whether the axis swap is a reflection, which reverses the winding order of triangles.
+  fn is_reflection(&self) -> bool {
+    let negations = self.axes.iter().filter(|axis| axis.sign() < 0.).count();
+    let [a, b, c] = [
+      self.axes[0].index(),
+      self.axes[1].index(),
+      self.axes[2].index(),
+    ];
+    // odd permutations are the ones swapping exactly two axes
+    let odd_permutation = a == 0 && b == 2 || a == 1 && b == 0 || a == 2 && c == 0;
+
+    odd_permutation != (negations % 2 == 1)
+  }
+
+  fn swap(&self, v: [f32; 3]) -> [f32; 3] {
+    let mut swapped = [0.; 3];
+
+    for (c, axis) in swapped.iter_mut().zip(&self.axes) {
+      *c = axis.sign() * v[axis.index()];
+    }
+
+    swapped
+  }
+
+  /// Apply the settings to all the meshes loaded from a file.
+  ///
+  /// Meshes are recentered together, so that they keep their relative placement.
+  pub fn apply(&self, meshes: Vec<Mesh>) -> Vec<Mesh> {
+    let reflection = self.is_reflection();
+    let normal_sign = if self.flip_normals { -1. } else { 1. };
+    // keep bitangents pointing the same way once normals and tangents are transformed
+    let handedness = if reflection != self.flip_normals {
+      -1.
+    } else {
+      1.
+    };
+    // a reflection reverses the winding; reverse it back so that front faces stay front faces
+    let flip_winding = self.flip_winding != reflection;
+
+    let mut meshes: Vec<_> = meshes
+      .into_iter()
+      .map(|mesh| {
+        let vertices = mesh
+          .vertices
+          .iter()
+          .map(|v| {
+            let pos = self.swap(v.pos());
+            let nor = self.swap(v.nor());
+            let tan = v.tangent();
+            let t = self.swap([tan[0], tan[1], tan[2]]);
+
+            MeshVertex::new(
+              [
+                pos[0] * self.scale,
+                pos[1] * self.scale,
+                pos[2] * self.scale,
+              ],
+              [
+                nor[0] * normal_sign,
+                nor[1] * normal_sign,
+                nor[2] * normal_sign,
+              ],
+            )
+            .with_uv(v.uv())
+            .with_tangent([t[0], t[1], t[2], tan[3] * handedness])
+            .with_color(v.color())
+          })
+          .collect::<Vec<_>>();
+
+        let mut indices = mesh.indices;
+        if flip_winding && mesh.mode == Mode::Triangle {
+          for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+          }
+        }
+
+        (vertices, indices, mesh.mode, mesh.attributes)
+      })
+      .collect();
+
+    let aabb = AABB::from_points(
+      meshes
+        .iter()
+        .flat_map(|(vertices, ..)| vertices.iter().map(MeshVertex::pos)),
+    );
+    let origin = match self.recenter {
+      Recenter::None => [0.; 3],
+      Recenter::Center => aabb.center(),
+      Recenter::Bottom => {
+        let center = aabb.center();
+        [center[0], aabb.min[1], center[2]]
+      }
+    };
+
+    if origin != [0.; 3] {
+      for (vertices, ..) in &mut meshes {
+        for v in vertices {
+          let pos = v.pos();
+          *v = MeshVertex::new(
+            [pos[0] - origin[0], pos[1] - origin[1], pos[2] - origin[2]],
+            v.nor(),
+          )
+          .with_uv(v.uv())
+          .with_tangent(v.tangent())
+          .with_color(v.color());
+        }
+      }
+    }
+
+    // rebuild the meshes to recompute their bounding volumes
+    meshes
+      .into_iter()
+      .map(|(vertices, indices, mode, attributes)| Mesh::new(vertices, indices, mode, attributes))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::entity::mesh::{cross3, dot3, procedural::MeshDescription, sub3};
+
+  #[test]
+  fn z_up_to_y_up() {
+    // the +Y axis of a Z-up file points backward once converted to Y-up
+    let description: MeshDescription =
+      serde_json::from_str(r#"{ "primitive": "plane", "width": 2, "depth": 4 }"#).unwrap();
+    let settings: ImportSettings =
+      serde_json::from_str(r#"{ "scale": 2, "axes": ["x", "z", "-y"], "recenter": "bottom" }"#)
+        .unwrap();
+    assert!(settings.validate().is_ok());
+    assert!(!settings.is_reflection());
+
+    let mesh = settings.apply(vec![description.build().unwrap()]).remove(0);
+
+    assert_eq!(mesh.aabb().min, [-2., 0., 0.]);
+    assert_eq!(mesh.aabb().max, [2., 8., 0.]);
+
+    for v in mesh.vertices() {
+      assert_eq!(v.nor(), [0., 0., -1.]);
+    }
+  }
+
+  #[test]
+  fn reflections_keep_front_faces() {
+    let description: MeshDescription = serde_json::from_str(r#"{ "primitive": "cube" }"#).unwrap();
+    let settings: ImportSettings = serde_json::from_str(r#"{ "axes": ["-x", "y", "z"] }"#).unwrap();
+    assert!(settings.is_reflection());
+
+    let mesh = settings.apply(vec![description.build().unwrap()]).remove(0);
+    let vertices = mesh.vertices();
+
+    // triangles still wind counter-clockwise around their outward normal
+    for triangle in mesh.indices().chunks_exact(3) {
+      let [a, b, c] = [
+        vertices[triangle[0] as usize].pos(),
+        vertices[triangle[1] as usize].pos(),
+        vertices[triangle[2] as usize].pos(),
+      ];
+      let face_normal = cross3(sub3(b, a), sub3(c, a));
+
+      assert!(dot3(face_normal, vertices[triangle[0] as usize].nor()) > 0.);
+    }
+  }
+
+  #[test]
+  fn invalid_settings() {
+    let settings: ImportSettings = serde_json::from_str(r#"{ "axes": ["x", "x", "z"] }"#).unwrap();
+    assert!(settings.validate().is_err());
+
+    let settings: ImportSettings = serde_json::from_str(r#"{ "scale": 0 }"#).unwrap();
+    assert!(settings.validate().is_err());
+
+    let settings: ImportSettings = serde_json::from_str(r#"{ "smoothing-angle": 270 }"#).unwrap();
+    assert!(settings.validate().is_err());
+
+    assert!(serde_json::from_str::<ImportSettings>(r#"{ "scael": 2 }"#).is_err());
+  }
+}
@@ -0,0 +1,488 @@
+//! PLY (Polygon File Format) parsing.
+//!
+//! Both the ASCII and binary (little and big endian) encodings are supported. Only the `vertex` and `face` elements
+//! are used; other elements are skipped.
+
+use super::{triangulation::ear_clip, Mesh, MeshVertex, VertexAttributes};
+
+/// Encoding of the body of a PLY file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+  Ascii,
+  BinaryLittleEndian,
+  BinaryBigEndian,
+}
+
+/// Scalar type of a property.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ScalarType {
+  I8,
+  U8,
+  I16,
+  U16,
+  I32,
+  U32,
+  F32,
+  F64,
+}
+
+impl ScalarType {
+  fn parse(name: &str) -> Result<Self, String> {
+    match name {
+      "char" | "int8" => Ok(ScalarType::I8),
+      "uchar" | "uint8" => Ok(ScalarType::U8),
+      "short" | "int16" => Ok(ScalarType::I16),
+      "ushort" | "uint16" => Ok(ScalarType::U16),
+      "int" | "int32" => Ok(ScalarType::I32),
+      "uint" | "uint32" => Ok(ScalarType::U32),
+      "float" | "float32" => Ok(ScalarType::F32),
+      "double" | "float64" => Ok(ScalarType::F64),
+      _ => Err(format!("unknown property type {}", name)),
+    }
+  }
+
+  fn size(self) -> usize {
+    match self {
+      ScalarType::I8 | ScalarType::U8 => 1,
+      ScalarType::I16 | ScalarType::U16 => 2,
+      ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+      ScalarType::F64 => 8,
+    }
+  }
+
+  /// Maximum value of integer types, used to normalize colors.
+  fn max(self) -> f64 {
+    match self {
+      ScalarType::I8 => i8::MAX as f64,
+      ScalarType::U8 => u8::MAX as f64,
+      ScalarType::I16 => i16::MAX as f64,
+      ScalarType::U16 => u16::MAX as f64,
+      ScalarType::I32 => i32::MAX as f64,
+      ScalarType::U32 => u32::MAX as f64,
+      ScalarType::F32 | ScalarType::F64 => 1.,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+  Scalar {
+    name: String,
+    ty: ScalarType,
+  },
+
+  List {
+    name: String,
+    count_ty: ScalarType,
+    item_ty: ScalarType,
+  },
+}
+
+impl Property {
+  fn name(&self) -> &str {
+    match self {
+      Property::Scalar { name, .. } | Property::List { name, .. } => name,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+  name: String,
+  count: usize,
+  properties: Vec<Property>,
+}
+
+/// Value of a property.
+#[derive(Clone, Debug)]
+enum Value {
+  Scalar(f64),
+  List(Vec<f64>),
+}
+
+/// Source of values, either ASCII tokens or binary data.
+struct Body<'a> {
+  format: Format,
+  bytes: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Body<'a> {
+  fn next_token(&mut self) -> Result<&'a str, String> {
+    while self.offset < self.bytes.len() && self.bytes[self.offset].is_ascii_whitespace() {
+      self.offset += 1;
+    }
+
+    let start = self.offset;
+    while self.offset < self.bytes.len() && !self.bytes[self.offset].is_ascii_whitespace() {
+      self.offset += 1;
+    }
+
+    if start == self.offset {
+      return Err("unexpected end of file".to_owned());
+    }
+
+    std::str::from_utf8(&self.bytes[start..self.offset]).map_err(|e| e.to_string())
+  }
+
+  fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+    if self.format == Format::Ascii {
+      let token = self.next_token()?;
+      return token
+        .parse()
+        .map_err(|_| format!("invalid number {}", token));
+    }
+
+    let size = ty.size();
+    let bytes = self
+      .bytes
+      .get(self.offset..self.offset + size)
+      .ok_or_else(|| "unexpected end of file".to_owned())?;
+    self.offset += size;
+
+    let mut buf = [0; 8];
+    buf[..size].copy_from_slice(bytes);
+    if self.format == Format::BinaryBigEndian {
+      buf[..size].reverse();
+    }
+
+    let value = match ty {
+      ScalarType::I8 => buf[0] as i8 as f64,
+      ScalarType::U8 => buf[0] as f64,
+      ScalarType::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+      ScalarType::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+      ScalarType::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+      ScalarType::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+      ScalarType::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+      ScalarType::F64 => f64::from_le_bytes(buf),
+    };
+
+    Ok(value)
+  }
+
+  fn read_property(&mut self, property: &Property) -> Result<Value, String> {
+    match *property {
+      Property::Scalar { ty, .. } => self.read(ty).map(Value::Scalar),
+
+      Property::List {
+        count_ty, item_ty, ..
+      } => {
+        let count = self.read(count_ty)?;
+        if count < 0. {
+          return Err(format!("negative list length {}", count));
+        }
+
+        let count = count as usize;
+        let items = (0..count)
+          .map(|_| self.read(item_ty))
+          .collect::<Result<_, _>>()?;
+
+        Ok(Value::List(items))
+      }
+    }
+  }
+}
+
+/// Parse the header of a PLY file; return its format, its elements and the offset of its body.
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+  let mut format = None;
+  let mut elements: Vec<Element> = Vec::new();
+  let mut offset = 0;
+
+  loop {
+    let end = bytes[offset..]
+      .iter()
+      .position(|b| *b == b'\n')
+      .map(|i| offset + i)
+      .ok_or_else(|| "unterminated header".to_owned())?;
+    let line = std::str::from_utf8(&bytes[offset..end]).map_err(|e| e.to_string())?;
+    offset = end + 1;
+
+    let mut tokens = line.split_whitespace();
+    match tokens.next() {
+      Some("format") => {
+        format = match tokens.next() {
+          Some("ascii") => Some(Format::Ascii),
+          Some("binary_little_endian") => Some(Format::BinaryLittleEndian),
+          Some("binary_big_endian") => Some(Format::BinaryBigEndian),
+          f => return Err(format!("unknown format {}", f.unwrap_or(""))),
+        };
+      }
+
+      Some("element") => {
+        let name = tokens.next().ok_or("missing element name")?;
+        let count = tokens
+          .next()
+          .and_then(|count| count.parse().ok())
+          .ok_or("missing element count")?;
+
+        elements.push(Element {
+          name: name.to_owned(),
+          count,
+          properties: Vec::new(),
+        });
+      }
+
+      Some("property") => {
+        let element = elements
+          .last_mut()
+          .ok_or("property declared outside of an element")?;
+        let property = match tokens.next() {
+          Some("list") => {
+            let count_ty = ScalarType::parse(tokens.next().unwrap_or(""))?;
+            let item_ty = ScalarType::parse(tokens.next().unwrap_or(""))?;
+            let name = tokens.next().ok_or("missing property name")?.to_owned();
+
+            Property::List {
+              name,
+              count_ty,
+              item_ty,
+            }
+          }
+
+          ty => {
+            let ty = ScalarType::parse(ty.unwrap_or(""))?;
+            let name = tokens.next().ok_or("missing property name")?.to_owned();
+
+            Property::Scalar { name, ty }
+          }
+        };
+
+        element.properties.push(property);
+      }
+
+      Some("end_header") => break,
+
+      // magic number, comment, obj_info and empty lines
+      _ => (),
+    }
+  }
+
+  let format = format.ok_or("missing format")?;
+  Ok((format, elements, offset))
+}
+
+/// Parse a PLY file into a triangle [`Mesh`]; missing normals are generated with `smoothing_angle`, in degrees.
+pub fn parse(bytes: &[u8], smoothing_angle: f32) -> Result<Mesh, String> {
+  if !bytes.starts_with(b"ply") {
+    return Err("not a PLY file".to_owned());
+  }
+
+  let (format, elements, offset) = parse_header(bytes)?;
+  let mut body = Body {
+    format,
+    bytes,
+    offset,
+  };
+
+  let mut vertices = Vec::new();
+  let mut triangles = Vec::new();
+  let mut attributes = VertexAttributes::default();
+  let mut has_normals = false;
+
+  for element in &elements {
+    let find = |names: &[&str]| {
+      element
+        .properties
+        .iter()
+        .position(|property| names.contains(&property.name()))
+    };
+
+    match element.name.as_str() {
+      "vertex" => {
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let uv = [
+          find(&["u", "s", "texture_u", "texture_s"]),
+          find(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        let color = [
+          find(&["red", "r"]),
+          find(&["green", "g"]),
+          find(&["blue", "b"]),
+        ];
+        let alpha = find(&["alpha", "a"]);
+
+        has_normals = normal.iter().all(Option::is_some);
+        attributes.uv = uv.iter().all(Option::is_some);
+        attributes.color = color.iter().all(Option::is_some);
+
+        for _ in 0..element.count {
+          let values = element
+            .properties
+            .iter()
+            .map(|property| body.read_property(property))
+            .collect::<Result<Vec<_>, _>>()?;
+          let scalar = |index: Option<usize>| match index.map(|i| &values[i]) {
+            Some(Value::Scalar(value)) => *value,
+            _ => 0.,
+          };
+          // colors are normalized according to their type
+          let channel = |index: Option<usize>, default: f64| match index {
+            Some(i) => match element.properties[i] {
+              Property::Scalar { ty, .. } => (scalar(index) / ty.max()) as f32,
+              Property::List { .. } => default as f32,
+            },
+            None => default as f32,
+          };
+
+          let mut vertex = MeshVertex::new(
+            [
+              scalar(position[0]) as f32,
+              scalar(position[1]) as f32,
+              scalar(position[2]) as f32,
+            ],
+            [
+              scalar(normal[0]) as f32,
+              scalar(normal[1]) as f32,
+              scalar(normal[2]) as f32,
+            ],
+          );
+
+          if attributes.uv {
+            vertex = vertex.with_uv([scalar(uv[0]) as f32, scalar(uv[1]) as f32]);
+          }
+
+          if attributes.color {
+            vertex = vertex.with_color([
+              channel(color[0], 1.),
+              channel(color[1], 1.),
+              channel(color[2], 1.),
+              channel(alpha, 1.),
+            ]);
+          }
+
+          vertices.push(vertex);
+        }
+      }
+
+      "face" => {
+        let indices = find(&["vertex_indices", "vertex_index"]).ok_or("faces without indices")?;
+
+        for _ in 0..element.count {
+          let mut polygon = Vec::new();
+
+          for (i, property) in element.properties.iter().enumerate() {
+            let value = body.read_property(property)?;
+
+            if let (true, Value::List(items)) = (i == indices, value) {
+              // indices can be signed; casting negative ones would silently wrap them
+              polygon = items
+                .into_iter()
+                .map(|index| {
+                  if index < 0. {
+                    Err(format!("negative vertex index {}", index))
+                  } else {
+                    Ok(index as usize)
+                  }
+                })
+                .collect::<Result<_, _>>()?;
+            }
+          }
+
+          if let Some(&index) = polygon.iter().find(|&&index| index >= vertices.len()) {
+            return Err(format!("vertex index {} out of bounds", index));
+          }
+
+          if polygon.len() == 3 {
+            triangles.push([polygon[0], polygon[1], polygon[2]]);
+          } else if polygon.len() > 3 {
+            let positions: Vec<_> = polygon.iter().map(|&index| vertices[index].pos()).collect();
+            triangles.extend(
+              ear_clip(&positions)
+                .into_iter()
+                .map(|[a, b, c]| [polygon[a], polygon[b], polygon[c]]),
+            );
+          }
+        }
+      }
+
+      _ => {
+        // skip unused elements
+        for _ in 0..element.count {
+          for property in &element.properties {
+            body.read_property(property)?;
+          }
+        }
+      }
+    }
+  }
+
+  Ok(Mesh::from_triangles(
+    &vertices,
+    &triangles,
+    if has_normals {
+      None
+    } else {
+      Some(smoothing_angle)
+    },
+    attributes,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const QUAD_HEADER: &str = "ply\nformat {}\ncomment a quad\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+  fn header(format: &str) -> Vec<u8> {
+    QUAD_HEADER.replace("{}", format).into_bytes()
+  }
+
+  #[test]
+  fn ascii_and_binary_agree() {
+    let mut ascii = header("ascii 1.0");
+    ascii.extend_from_slice(
+      b"0 0 0 255 0 0\n1 0 0 255 0 0\n1 1 0 255 0 0\n0 1 0 255 0 0\n4 0 1 2 3\n",
+    );
+
+    let mut binary = header("binary_big_endian 1.0");
+    for [x, y] in [[0f32, 0.], [1., 0.], [1., 1.], [0., 1.]].iter() {
+      binary.extend_from_slice(&x.to_be_bytes());
+      binary.extend_from_slice(&y.to_be_bytes());
+      binary.extend_from_slice(&0f32.to_be_bytes());
+      binary.extend_from_slice(&[255, 0, 0]);
+    }
+    binary.push(4);
+    for index in 0..4i32 {
+      binary.extend_from_slice(&index.to_be_bytes());
+    }
+
+    let ascii = parse(&ascii, Mesh::DEFAULT_SMOOTHING_ANGLE).unwrap();
+    let binary = parse(&binary, Mesh::DEFAULT_SMOOTHING_ANGLE).unwrap();
+
+    assert_eq!(ascii.indices(), binary.indices());
+    assert_eq!(ascii.indices().len(), 6);
+    assert_eq!(ascii.vertices().len(), 4);
+    assert!(ascii.attributes().color);
+
+    for (a, b) in ascii.vertices().iter().zip(binary.vertices()) {
+      assert_eq!(a.pos(), b.pos());
+      assert_eq!(a.color(), [1., 0., 0., 1.]);
+      assert_eq!(b.color(), [1., 0., 0., 1.]);
+    }
+  }
+
+  #[test]
+  fn negative_indices() {
+    let mut ascii = header("ascii 1.0");
+    ascii.extend_from_slice(
+      b"0 0 0 255 0 0\n1 0 0 255 0 0\n1 1 0 255 0 0\n0 1 0 255 0 0\n4 0 1 -2 3\n",
+    );
+
+    let mut binary = header("binary_little_endian 1.0");
+    binary.extend(std::iter::repeat(0).take(4 * 15));
+    binary.push(4);
+    for index in &[0i32, 1, -2, 3] {
+      binary.extend_from_slice(&index.to_le_bytes());
+    }
+
+    for bytes in &[ascii, binary] {
+      assert_eq!(
+        parse(bytes, Mesh::DEFAULT_SMOOTHING_ANGLE).unwrap_err(),
+        "negative vertex index -2"
+      );
+    }
+  }
+}
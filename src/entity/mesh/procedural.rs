@@ -0,0 +1,595 @@
+//! Procedural meshes.
+//!
+//! Procedural meshes are described in JSON by the name of their primitive and its parameters, such as:
+//!
+//! ```json
+//! { "primitive": "uv-sphere", "radius": 2, "segments": 64, "rings": 32 }
+//! ```
+//!
+//! Missing parameters take their default value. All the primitives are centered on the origin, with +Y up, and have
+//! outward-facing counter-clockwise triangles, normals, texture coordinates and tangents. Descriptions yielding more
+//! than [`MAX_VERTICES`] vertices are rejected.
+
+use super::{
+  add3, cross3, dot3, normalize3, scale3, sub3, Mesh, MeshIndex, MeshVertex, VertexAttributes,
+};
+use luminance::tess::Mode;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, f32::consts::PI};
+
+/// Maximum number of vertices of a procedural mesh.
+pub const MAX_VERTICES: MeshIndex = 1 << 22;
+
+/// Description of a procedural mesh.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "primitive", rename_all = "kebab-case")]
+pub enum MeshDescription {
+  Plane(Plane),
+  Grid(Grid),
+  Cube(Cube),
+  UvSphere(UvSphere),
+  Icosphere(Icosphere),
+  Torus(Torus),
+  Cylinder(Cylinder),
+}
+
+/// A plane in the XZ plane, facing +Y.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Plane {
+  pub width: f32,
+  pub depth: f32,
+}
+
+impl Default for Plane {
+  fn default() -> Self {
+    Plane {
+      width: 1.,
+      depth: 1.,
+    }
+  }
+}
+
+/// A plane in the XZ plane, facing +Y, subdivided in cells.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Grid {
+  pub width: f32,
+  pub depth: f32,
+  pub width_segments: u32,
+  pub depth_segments: u32,
+}
+
+impl Default for Grid {
+  fn default() -> Self {
+    Grid {
+      width: 1.,
+      depth: 1.,
+      width_segments: 10,
+      depth_segments: 10,
+    }
+  }
+}
+
+/// A cube, with faces optionally subdivided.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Cube {
+  pub size: f32,
+  pub segments: u32,
+}
+
+impl Default for Cube {
+  fn default() -> Self {
+    Cube {
+      size: 1.,
+      segments: 1,
+    }
+  }
+}
+
+/// A sphere made of meridians (segments) and parallels (rings).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct UvSphere {
+  pub radius: f32,
+  pub segments: u32,
+  pub rings: u32,
+}
+
+impl Default for UvSphere {
+  fn default() -> Self {
+    UvSphere {
+      radius: 1.,
+      segments: 32,
+      rings: 16,
+    }
+  }
+}
+
+/// A sphere made by subdividing an icosahedron.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Icosphere {
+  pub radius: f32,
+  pub subdivisions: u32,
+}
+
+impl Default for Icosphere {
+  fn default() -> Self {
+    Icosphere {
+      radius: 1.,
+      subdivisions: 2,
+    }
+  }
+}
+
+/// A torus lying in the XZ plane.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Torus {
+  /// Distance from the center of the torus to the center of the tube.
+  pub major_radius: f32,
+  /// Radius of the tube.
+  pub minor_radius: f32,
+  pub major_segments: u32,
+  pub minor_segments: u32,
+}
+
+impl Default for Torus {
+  fn default() -> Self {
+    Torus {
+      major_radius: 1.,
+      minor_radius: 0.25,
+      major_segments: 32,
+      minor_segments: 16,
+    }
+  }
+}
+
+/// A cylinder along the Y axis.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Cylinder {
+  pub radius: f32,
+  pub height: f32,
+  pub segments: u32,
+  pub height_segments: u32,
+  /// Whether the cylinder is closed by caps.
+  pub caps: bool,
+}
+
+impl Default for Cylinder {
+  fn default() -> Self {
+    Cylinder {
+      radius: 1.,
+      height: 2.,
+      segments: 32,
+      height_segments: 1,
+      caps: true,
+    }
+  }
+}
+
+impl MeshDescription {
+  /// Build the mesh.
+  ///
+  /// Segment counts lower than what makes sense for a primitive are raised to the minimum. Descriptions yielding too
+  /// many vertices are rejected before anything is allocated.
+  pub fn build(&self) -> Result<Mesh, String> {
+    match self.vertex_count() {
+      Some(count) if count <= MAX_VERTICES => (),
+      _ => {
+        return Err(format!(
+          "procedural mesh has too many vertices (at most {} are allowed)",
+          MAX_VERTICES
+        ))
+      }
+    }
+
+    let mut builder = Builder::default();
+
+    match *self {
+      MeshDescription::Plane(Plane { width, depth }) => builder.grid(width, depth, 1, 1),
+
+      MeshDescription::Grid(Grid {
+        width,
+        depth,
+        width_segments,
+        depth_segments,
+      }) => builder.grid(width, depth, width_segments, depth_segments),
+
+      MeshDescription::Cube(Cube { size, segments }) => builder.cube(size, segments),
+
+      MeshDescription::UvSphere(UvSphere {
+        radius,
+        segments,
+        rings,
+      }) => builder.uv_sphere(radius, segments, rings),
+
+      MeshDescription::Icosphere(Icosphere {
+        radius,
+        subdivisions,
+      }) => builder.icosphere(radius, subdivisions),
+
+      MeshDescription::Torus(Torus {
+        major_radius,
+        minor_radius,
+        major_segments,
+        minor_segments,
+      }) => builder.torus(major_radius, minor_radius, major_segments, minor_segments),
+
+      MeshDescription::Cylinder(Cylinder {
+        radius,
+        height,
+        segments,
+        height_segments,
+        caps,
+      }) => builder.cylinder(radius, height, segments, height_segments, caps),
+    }
+
+    Ok(builder.build())
+  }
+
+  /// Number of vertices of the mesh, or [`None`] if it doesn’t fit in a [`MeshIndex`].
+  fn vertex_count(&self) -> Option<MeshIndex> {
+    // vertices of a surface sampled on a grid
+    let surface = |u_segments: u32, v_segments: u32| {
+      u_segments
+        .checked_add(1)?
+        .checked_mul(v_segments.checked_add(1)?)
+    };
+
+    match *self {
+      MeshDescription::Plane(_) => surface(1, 1),
+
+      MeshDescription::Grid(Grid {
+        width_segments,
+        depth_segments,
+        ..
+      }) => surface(width_segments.max(1), depth_segments.max(1)),
+
+      MeshDescription::Cube(Cube { segments, .. }) => {
+        surface(segments.max(1), segments.max(1))?.checked_mul(6)
+      }
+
+      MeshDescription::UvSphere(UvSphere {
+        segments, rings, ..
+      }) => surface(segments.max(3), rings.max(2)),
+
+      // every subdivision splits each of the 20 faces in four, and faces don’t share vertices
+      MeshDescription::Icosphere(Icosphere { subdivisions, .. }) => {
+        4u32.checked_pow(subdivisions)?.checked_mul(20 * 3)
+      }
+
+      MeshDescription::Torus(Torus {
+        major_segments,
+        minor_segments,
+        ..
+      }) => surface(major_segments.max(3), minor_segments.max(3)),
+
+      MeshDescription::Cylinder(Cylinder {
+        segments,
+        height_segments,
+        caps,
+        ..
+      }) => {
+        let side = surface(segments.max(3), height_segments.max(1))?;
+        let caps = if caps {
+          surface(segments.max(3), 1)?.checked_mul(2)?
+        } else {
+          0
+        };
+
+        side.checked_add(caps)
+      }
+    }
+  }
+}
+
+/// Accumulate vertices and triangles of a procedural mesh.
+#[derive(Debug, Default)]
+struct Builder {
+  vertices: Vec<MeshVertex>,
+  indices: Vec<MeshIndex>,
+}
+
+impl Builder {
+  /// Add a parametric surface, sampled on a `u_segments × v_segments` grid.
+  ///
+  /// `f` maps `(u, v)` in `[0; 1]²` to a position, a normal and texture coordinates. The surface must be parametrized
+  /// so that `∂p/∂u × ∂p/∂v` points outward.
+  fn surface(
+    &mut self,
+    u_segments: u32,
+    v_segments: u32,
+    f: impl Fn(f32, f32) -> ([f32; 3], [f32; 3], [f32; 2]),
+  ) {
+    // the vertex count of the whole mesh is checked before building it, so indices can’t overflow
+    let first = self.vertices.len() as MeshIndex;
+    let row = u_segments + 1;
+
+    for j in 0..=v_segments {
+      for i in 0..=u_segments {
+        let (pos, nor, uv) = f(i as f32 / u_segments as f32, j as f32 / v_segments as f32);
+        self.vertices.push(MeshVertex::new(pos, nor).with_uv(uv));
+      }
+    }
+
+    for j in 0..v_segments {
+      for i in 0..u_segments {
+        let a = first + j * row + i;
+        let (b, c, d) = (a + 1, a + row + 1, a + row);
+        self.indices.extend_from_slice(&[a, b, c, a, c, d]);
+      }
+    }
+  }
+
+  fn grid(&mut self, width: f32, depth: f32, width_segments: u32, depth_segments: u32) {
+    self.surface(width_segments.max(1), depth_segments.max(1), |u, v| {
+      (
+        [(u - 0.5) * width, 0., (0.5 - v) * depth],
+        [0., 1., 0.],
+        [u, v],
+      )
+    });
+  }
+
+  fn cube(&mut self, size: f32, segments: u32) {
+    // normal and tangent frame of each face, such that du × dv = normal
+    let faces = [
+      ([1., 0., 0.], [0., 0., -1.], [0., 1., 0.]),
+      ([-1., 0., 0.], [0., 0., 1.], [0., 1., 0.]),
+      ([0., 1., 0.], [1., 0., 0.], [0., 0., -1.]),
+      ([0., -1., 0.], [1., 0., 0.], [0., 0., 1.]),
+      ([0., 0., 1.], [1., 0., 0.], [0., 1., 0.]),
+      ([0., 0., -1.], [-1., 0., 0.], [0., 1., 0.]),
+    ];
+    let segments = segments.max(1);
+
+    for &(n, du, dv) in &faces {
+      self.surface(segments, segments, |u, v| {
+        let p = add3(
+          scale3(n, 0.5),
+          add3(scale3(du, u - 0.5), scale3(dv, v - 0.5)),
+        );
+        (scale3(p, size), n, [u, v])
+      });
+    }
+  }
+
+  fn uv_sphere(&mut self, radius: f32, segments: u32, rings: u32) {
+    self.surface(segments.max(3), rings.max(2), |u, v| {
+      let (phi, theta) = (u * 2. * PI, v * PI);
+      let n = [
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+      ];
+      (scale3(n, radius), n, [u, 1. - v])
+    });
+  }
+
+  fn icosphere(&mut self, radius: f32, subdivisions: u32) {
+    let t = (1. + 5f32.sqrt()) / 2.;
+    let mut positions: Vec<[f32; 3]> = [
+      [-1., t, 0.],
+      [1., t, 0.],
+      [-1., -t, 0.],
+      [1., -t, 0.],
+      [0., -1., t],
+      [0., 1., t],
+      [0., -1., -t],
+      [0., 1., -t],
+      [t, 0., -1.],
+      [t, 0., 1.],
+      [-t, 0., -1.],
+      [-t, 0., 1.],
+    ]
+    .iter()
+    .map(|&p| normalize3(p))
+    .collect();
+    let mut triangles: Vec<[usize; 3]> = vec![
+      [0, 11, 5],
+      [0, 5, 1],
+      [0, 1, 7],
+      [0, 7, 10],
+      [0, 10, 11],
+      [1, 5, 9],
+      [5, 11, 4],
+      [11, 10, 2],
+      [10, 7, 6],
+      [7, 1, 8],
+      [3, 9, 4],
+      [3, 4, 2],
+      [3, 2, 6],
+      [3, 6, 8],
+      [3, 8, 9],
+      [4, 9, 5],
+      [2, 4, 11],
+      [6, 2, 10],
+      [8, 6, 7],
+      [9, 8, 1],
+    ];
+
+    // every subdivision splits each triangle in four, sharing the new midpoints between adjacent triangles
+    for _ in 0..subdivisions {
+      let mut midpoints = HashMap::new();
+      let mut midpoint = |a: usize, b: usize| {
+        *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+          positions.push(normalize3(scale3(add3(positions[a], positions[b]), 0.5)));
+          positions.len() - 1
+        })
+      };
+
+      triangles = triangles
+        .iter()
+        .flat_map(|&[a, b, c]| {
+          let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+          vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        })
+        .collect();
+    }
+
+    // faces are flat-mapped, as vertices on the texture seam can’t be shared
+    for [a, b, c] in triangles {
+      let mut corners = [positions[a], positions[b], positions[c]];
+      let face_normal = cross3(sub3(corners[1], corners[0]), sub3(corners[2], corners[0]));
+      if dot3(face_normal, corners[0]) < 0. {
+        corners.swap(1, 2);
+      }
+
+      let mut uvs = [[0.; 2]; 3];
+      for (uv, n) in uvs.iter_mut().zip(&corners) {
+        *uv = [0.5 + n[2].atan2(n[0]) / (2. * PI), 0.5 + n[1].asin() / PI];
+      }
+
+      // fix triangles crossing the seam, whose U would otherwise wrap around the whole texture
+      let max_u = uvs.iter().map(|uv| uv[0]).fold(0., f32::max);
+      for uv in &mut uvs {
+        if max_u - uv[0] > 0.5 {
+          uv[0] += 1.;
+        }
+      }
+
+      let first = self.vertices.len() as MeshIndex;
+      for (n, uv) in corners.iter().zip(&uvs) {
+        self
+          .vertices
+          .push(MeshVertex::new(scale3(*n, radius), *n).with_uv(*uv));
+      }
+      self
+        .indices
+        .extend_from_slice(&[first, first + 1, first + 2]);
+    }
+  }
+
+  fn torus(
+    &mut self,
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+  ) {
+    self.surface(major_segments.max(3), minor_segments.max(3), |u, v| {
+      let (phi, psi) = (u * 2. * PI, v * 2. * PI);
+      let n = [psi.cos() * phi.cos(), -psi.sin(), psi.cos() * phi.sin()];
+      let center = [major_radius * phi.cos(), 0., major_radius * phi.sin()];
+      (add3(center, scale3(n, minor_radius)), n, [u, v])
+    });
+  }
+
+  fn cylinder(
+    &mut self,
+    radius: f32,
+    height: f32,
+    segments: u32,
+    height_segments: u32,
+    caps: bool,
+  ) {
+    let segments = segments.max(3);
+
+    self.surface(segments, height_segments.max(1), |u, v| {
+      let phi = u * 2. * PI;
+      let n = [phi.cos(), 0., phi.sin()];
+      (
+        [radius * n[0], height * (0.5 - v), radius * n[2]],
+        n,
+        [u, v],
+      )
+    });
+
+    if caps {
+      for &(y, ny) in &[(height * 0.5, 1.), (-height * 0.5, -1.)] {
+        self.surface(segments, 1, |u, v| {
+          let phi = u * 2. * PI;
+          // the top cap goes from the center to the rim, the bottom one the other way around, to face outward
+          let rho = if ny > 0. { v } else { 1. - v };
+          let (x, z) = (rho * phi.cos(), rho * phi.sin());
+          (
+            [radius * x, y, radius * z],
+            [0., ny, 0.],
+            [0.5 + 0.5 * x, 0.5 + 0.5 * z],
+          )
+        });
+      }
+    }
+  }
+
+  fn build(mut self) -> Mesh {
+    Mesh::generate_tangents(&mut self.vertices, &self.indices);
+
+    let attributes = VertexAttributes {
+      uv: true,
+      tangent: true,
+      color: false,
+    };
+
+    Mesh::new(self.vertices, self.indices, Mode::Triangle, attributes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Check that all the non-degenerated triangles of a mesh face the same way as their vertex normals.
+  fn assert_outward(mesh: &Mesh) {
+    let vertices = mesh.vertices();
+
+    for triangle in mesh.indices().chunks_exact(3) {
+      let [a, b, c] = [
+        vertices[triangle[0] as usize],
+        vertices[triangle[1] as usize],
+        vertices[triangle[2] as usize],
+      ];
+      let normal = cross3(sub3(b.pos(), a.pos()), sub3(c.pos(), a.pos()));
+
+      if dot3(normal, normal) > 1e-10 {
+        assert!(dot3(normal, add3(add3(a.nor(), b.nor()), c.nor())) > 0.);
+      }
+    }
+  }
+
+  #[test]
+  fn primitives_face_outward() {
+    let descriptions = [
+      r#"{ "primitive": "cube", "segments": 2 }"#,
+      r#"{ "primitive": "uv-sphere" }"#,
+      r#"{ "primitive": "icosphere", "subdivisions": 1 }"#,
+      r#"{ "primitive": "torus", "minor-radius": 0.1 }"#,
+      r#"{ "primitive": "cylinder" }"#,
+    ];
+
+    for description in &descriptions {
+      let description: MeshDescription = serde_json::from_str(description).unwrap();
+      assert_outward(&description.build().unwrap());
+    }
+  }
+
+  #[test]
+  fn grid_size() {
+    let description: MeshDescription =
+      serde_json::from_str(r#"{ "primitive": "grid", "width-segments": 4, "depth-segments": 2 }"#)
+        .unwrap();
+    let mesh = description.build().unwrap();
+
+    assert_eq!(mesh.vertices().len(), 5 * 3);
+    assert_eq!(mesh.indices().len(), 4 * 2 * 6);
+    assert!(mesh.vertices().iter().all(|v| v.nor() == [0., 1., 0.]));
+  }
+
+  #[test]
+  fn too_many_vertices() {
+    let descriptions = [
+      r#"{ "primitive": "icosphere", "subdivisions": 20 }"#,
+      r#"{ "primitive": "grid", "width-segments": 100000, "depth-segments": 100000 }"#,
+      r#"{ "primitive": "cube", "segments": 4294967295 }"#,
+    ];
+
+    for description in &descriptions {
+      let description: MeshDescription = serde_json::from_str(description).unwrap();
+      assert!(description.build().is_err());
+    }
+  }
+}
@@ -0,0 +1,195 @@
+//! STL (stereolithography) parsing.
+//!
+//! Both the ASCII and binary encodings are supported. STL only stores triangles along with their facet normal, so
+//! meshes decoded from STL are flat-shaded.
+
+use super::{cross3, normalize3, sub3, Mesh, MeshVertex, VertexAttributes};
+use std::convert::TryInto as _;
+
+/// Size of the header of binary STL files.
+const BINARY_HEADER_SIZE: usize = 80;
+
+/// Size of a triangle in binary STL files: normal, three vertices and attribute byte count.
+const BINARY_TRIANGLE_SIZE: usize = 4 * 3 * 4 + 2;
+
+/// Parse a STL file into a triangle [`Mesh`].
+pub fn parse(bytes: &[u8]) -> Result<Mesh, String> {
+  // binary files can start with `solid` too; their size is what tells them apart
+  let facets = if is_binary(bytes) {
+    parse_binary(bytes)?
+  } else {
+    let src = std::str::from_utf8(bytes).map_err(|e| e.to_string())?;
+    parse_ascii(src)?
+  };
+
+  let mut vertices = Vec::with_capacity(facets.len() * 3);
+  let mut triangles = Vec::with_capacity(facets.len());
+
+  for (normal, corners) in facets {
+    // some exporters don’t bother writing normals
+    let normal = if normal == [0.; 3] {
+      normalize3(cross3(
+        sub3(corners[1], corners[0]),
+        sub3(corners[2], corners[0]),
+      ))
+    } else {
+      normalize3(normal)
+    };
+
+    let first = vertices.len();
+    vertices.extend(corners.iter().map(|&pos| MeshVertex::new(pos, normal)));
+    triangles.push([first, first + 1, first + 2]);
+  }
+
+  Ok(Mesh::from_triangles(
+    &vertices,
+    &triangles,
+    None,
+    VertexAttributes::default(),
+  ))
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+  if bytes.len() < BINARY_HEADER_SIZE + 4 {
+    return false;
+  }
+
+  let count = u32::from_le_bytes([
+    bytes[BINARY_HEADER_SIZE],
+    bytes[BINARY_HEADER_SIZE + 1],
+    bytes[BINARY_HEADER_SIZE + 2],
+    bytes[BINARY_HEADER_SIZE + 3],
+  ]) as usize;
+
+  bytes.len() == BINARY_HEADER_SIZE + 4 + count * BINARY_TRIANGLE_SIZE
+    || !bytes.starts_with(b"solid")
+}
+
+type Facet = ([f32; 3], [[f32; 3]; 3]);
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Facet>, String> {
+  let body = &bytes[BINARY_HEADER_SIZE + 4..];
+
+  if body.len() % BINARY_TRIANGLE_SIZE != 0 {
+    return Err("truncated binary STL".to_owned());
+  }
+
+  let read_vec3 = |bytes: &[u8]| -> [f32; 3] {
+    let mut v = [0.; 3];
+    for (c, chunk) in v.iter_mut().zip(bytes.chunks_exact(4)) {
+      *c = f32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    v
+  };
+
+  let facets = body
+    .chunks_exact(BINARY_TRIANGLE_SIZE)
+    .map(|triangle| {
+      (
+        read_vec3(&triangle[0..12]),
+        [
+          read_vec3(&triangle[12..24]),
+          read_vec3(&triangle[24..36]),
+          read_vec3(&triangle[36..48]),
+        ],
+      )
+    })
+    .collect();
+
+  Ok(facets)
+}
+
+fn parse_ascii(src: &str) -> Result<Vec<Facet>, String> {
+  let mut tokens = src.split_whitespace();
+  let mut facets = Vec::new();
+  let mut normal = [0.; 3];
+  let mut corners = Vec::with_capacity(3);
+
+  while let Some(token) = tokens.next() {
+    match token {
+      "normal" => normal = read_vec3(&mut tokens)?,
+      "vertex" => corners.push(read_vec3(&mut tokens)?),
+
+      "endfacet" => {
+        if corners.len() != 3 {
+          return Err(format!("facet with {} vertices", corners.len()));
+        }
+
+        facets.push((normal, [corners[0], corners[1], corners[2]]));
+        normal = [0.; 3];
+        corners.clear();
+      }
+
+      // solid, facet, outer, loop, endloop, endsolid and the name of the solid
+      _ => (),
+    }
+  }
+
+  Ok(facets)
+}
+
+fn read_vec3(tokens: &mut std::str::SplitWhitespace) -> Result<[f32; 3], String> {
+  let mut v = [0.; 3];
+
+  for c in &mut v {
+    let token = tokens.next().ok_or("unexpected end of file")?;
+    *c = token
+      .parse()
+      .map_err(|_| format!("invalid number {}", token))?;
+  }
+
+  Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ascii_and_binary_agree() {
+    let ascii = "solid quad
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 1 1 0
+  endloop
+endfacet
+facet normal 0 0 0
+  outer loop
+    vertex 0 0 0
+    vertex 1 1 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid quad
+";
+
+    let mut binary = vec![0; BINARY_HEADER_SIZE];
+    binary[..5].copy_from_slice(b"solid");
+    binary.extend_from_slice(&2u32.to_le_bytes());
+    for facet in &[
+      [0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 1., 0.],
+      [0., 0., 0., 0., 0., 0., 1., 1., 0., 0., 1., 0.],
+    ] {
+      for c in facet.iter() {
+        binary.extend_from_slice(&(*c as f32).to_le_bytes());
+      }
+      binary.extend_from_slice(&[0, 0]);
+    }
+
+    let ascii = parse(ascii.as_bytes()).unwrap();
+    let binary = parse(&binary).unwrap();
+
+    // both facets are coplanar, so their shared vertices are merged
+    assert_eq!(ascii.vertices().len(), 4);
+    assert_eq!(ascii.indices().len(), 6);
+    assert_eq!(ascii.indices(), binary.indices());
+
+    for (a, b) in ascii.vertices().iter().zip(binary.vertices()) {
+      assert_eq!(a.pos(), b.pos());
+      assert_eq!(a.nor(), [0., 0., 1.]);
+      assert_eq!(b.nor(), [0., 0., 1.]);
+    }
+  }
+}
@@ -0,0 +1,223 @@
+//! Polygon triangulation of OBJ sources.
+//!
+//! The OBJ parser only knows how to fan-triangulate faces, which breaks concave polygons, and it doesn’t support point
+//! primitives (`p`). Instead of parsing OBJ twice, the source is rewritten prior to parsing so that faces are already
+//! triangulated by ear clipping, polylines are split into segments and points are expressed as single-corner faces.
+
+use std::fmt::Write as _;
+
+/// Rewrite an OBJ source so that the parser only sees triangles, segments and single-corner faces.
+pub fn triangulate_obj(src: &str) -> String {
+  let mut positions: Vec<[f32; 3]> = Vec::new();
+  let mut output = String::with_capacity(src.len());
+
+  for line in src.lines() {
+    // comments are dropped from rewritten lines only
+    let content = line.split('#').next().unwrap_or("");
+    let mut tokens = content.split_whitespace();
+
+    match tokens.next() {
+      Some("v") => {
+        let mut position = [0.; 3];
+        for (c, token) in position.iter_mut().zip(tokens) {
+          *c = token.parse().unwrap_or(0.);
+        }
+
+        positions.push(position);
+        let _ = writeln!(output, "{}", line);
+      }
+
+      Some("f") => {
+        let corners: Vec<_> = tokens.collect();
+
+        if corners.len() <= 3 {
+          let _ = writeln!(output, "{}", line);
+        } else {
+          let polygon: Vec<_> = corners
+            .iter()
+            .map(|corner| resolve_position(&positions, corner))
+            .collect();
+
+          for [a, b, c] in ear_clip(&polygon) {
+            let _ = writeln!(output, "f {} {} {}", corners[a], corners[b], corners[c]);
+          }
+        }
+      }
+
+      Some("l") => {
+        let corners: Vec<_> = tokens.collect();
+
+        if corners.len() <= 2 {
+          let _ = writeln!(output, "{}", line);
+        } else {
+          for segment in corners.windows(2) {
+            let _ = writeln!(output, "l {} {}", segment[0], segment[1]);
+          }
+        }
+      }
+
+      Some("p") => {
+        for corner in tokens {
+          let _ = writeln!(output, "f {}", corner);
+        }
+      }
+
+      _ => {
+        let _ = writeln!(output, "{}", line);
+      }
+    }
+  }
+
+  output
+}
+
+/// Find the position referenced by a face corner (`v`, `v/vt`, `v//vn` or `v/vt/vn`).
+///
+/// Invalid indices resolve to the origin; the parser will report them anyway.
+fn resolve_position(positions: &[[f32; 3]], corner: &str) -> [f32; 3] {
+  let index = corner
+    .split('/')
+    .next()
+    .and_then(|index| index.parse::<isize>().ok())
+    .and_then(|index| {
+      if index < 0 {
+        positions.len().checked_sub(index.unsigned_abs())
+      } else {
+        (index as usize).checked_sub(1)
+      }
+    });
+
+  index
+    .and_then(|index| positions.get(index).copied())
+    .unwrap_or([0.; 3])
+}
+
+/// Triangulate a (possibly concave) planar polygon by ear clipping.
+///
+/// The returned triangles index the polygon and keep its winding. If no ear can be found (self-intersecting or
+/// degenerate polygons), the remaining polygon is fan-triangulated.
+pub fn ear_clip(polygon: &[[f32; 3]]) -> Vec<[usize; 3]> {
+  let points = project(polygon);
+  let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+  let mut triangles = Vec::with_capacity(polygon.len().saturating_sub(2));
+
+  // orientation of the polygon in the projection plane; ears must turn the same way
+  let orientation = signed_area(&points).signum();
+
+  while remaining.len() > 3 {
+    let n = remaining.len();
+    let ear = (0..n).find(|&i| {
+      let (a, b, c) = (
+        remaining[(i + n - 1) % n],
+        remaining[i],
+        remaining[(i + 1) % n],
+      );
+
+      is_convex(points[a], points[b], points[c], orientation)
+        && remaining
+          .iter()
+          .filter(|&&p| p != a && p != b && p != c)
+          .all(|&p| !in_triangle(points[p], points[a], points[b], points[c]))
+    });
+
+    match ear {
+      Some(i) => {
+        triangles.push([
+          remaining[(i + n - 1) % n],
+          remaining[i],
+          remaining[(i + 1) % n],
+        ]);
+        remaining.remove(i);
+      }
+
+      None => break,
+    }
+  }
+
+  for i in 1..remaining.len().saturating_sub(1) {
+    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+  }
+
+  triangles
+}
+
+/// Project a polygon onto the axis-aligned plane it’s the most parallel to.
+fn project(polygon: &[[f32; 3]]) -> Vec<[f32; 2]> {
+  // Newell’s method gives a robust normal even for concave polygons
+  let mut normal = [0.; 3];
+  for (i, p) in polygon.iter().enumerate() {
+    let q = polygon[(i + 1) % polygon.len()];
+    normal[0] += (p[1] - q[1]) * (p[2] + q[2]);
+    normal[1] += (p[2] - q[2]) * (p[0] + q[0]);
+    normal[2] += (p[0] - q[0]) * (p[1] + q[1]);
+  }
+
+  let [x, y, z] = [normal[0].abs(), normal[1].abs(), normal[2].abs()];
+  let (u, v) = if z >= x && z >= y {
+    (0, 1)
+  } else if y >= x {
+    (2, 0)
+  } else {
+    (1, 2)
+  };
+
+  polygon.iter().map(|p| [p[u], p[v]]).collect()
+}
+
+fn signed_area(points: &[[f32; 2]]) -> f32 {
+  points
+    .iter()
+    .enumerate()
+    .map(|(i, p)| {
+      let q = points[(i + 1) % points.len()];
+      p[0] * q[1] - q[0] * p[1]
+    })
+    .sum()
+}
+
+fn cross(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+  (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+fn is_convex(a: [f32; 2], b: [f32; 2], c: [f32; 2], orientation: f32) -> bool {
+  cross(a, b, c) * orientation > 0.
+}
+
+fn in_triangle(p: [f32; 2], a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> bool {
+  let (d1, d2, d3) = (cross(a, b, p), cross(b, c, p), cross(c, a, p));
+  let has_neg = d1 < 0. || d2 < 0. || d3 < 0.;
+  let has_pos = d1 > 0. || d2 > 0. || d3 > 0.;
+
+  !(has_neg && has_pos)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ear_clip_concave() {
+    // an arrow-like concave quad: the fan from the reflex vertex 3 would be wrong from vertex 0
+    let polygon = [[0., 0., 0.], [2., 1., 0.], [0., 2., 0.], [0.5, 1., 0.]];
+    let triangles = ear_clip(&polygon);
+
+    assert_eq!(triangles.len(), 2);
+
+    // no triangle can be clockwise in a counter-clockwise polygon
+    let points = project(&polygon);
+    for [a, b, c] in triangles {
+      assert!(cross(points[a], points[b], points[c]) > 0.);
+    }
+  }
+
+  #[test]
+  fn rewrite_primitives() {
+    let src = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\nl 1 2 3\np 1 -1\n";
+    let rewritten = triangulate_obj(src);
+
+    assert_eq!(
+      rewritten,
+      "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 4 1 2\nf 2 3 4\nl 1 2\nl 2 3\nf 1\nf -1\n"
+    );
+  }
+}
@@ -9,7 +9,13 @@
 //!   behavior of the parameter as a function of time. Those parameters implement different kind of animation
 //!   parameters, depending on your need (constant, linear, cosine, Bézier, etc.).
 
-use crate::entity::{decoder::Decoder, Entity, EntityEvent};
+use crate::{
+  entity::{
+    decoder::{Decoded, Decoder, DecodingMetadata},
+    Entity,
+  },
+  system::resource::ResourceManager,
+};
 use colored::Colorize as _;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::{collections::HashMap, error, fmt, fs, io, path::Path, sync::Arc};
@@ -85,32 +91,32 @@ impl Decoder for ParameterDecoder {
   type Err = ParameterError;
 
   fn load_from_file(
-    resources: &mut crate::system::resource::ResourceManager<super::Entity>,
-    publisher: &mut impl crate::system::Publisher<super::EntityEvent>,
+    _: &ResourceManager<Entity>,
     path: impl AsRef<Path>,
-  ) -> Result<(), Self::Err> {
+  ) -> Result<Vec<Decoded>, Self::Err> {
     let path = path.as_ref();
+    let params = Parameter::load_from_file(path)?;
 
-    match Parameter::load_from_file(path) {
-      Ok(params) => {
-        let path = path.display().to_string().purple().italic();
-        log::info!("{} parameters at {}", "loaded".green().bold(), path);
-
-        // check each parameter and create handle if not already existing; update otherwise
-        for (name, param) in params {
-          log::debug!("  found parameter {}: {:?}", name.purple().italic(), param);
-          let entity = Entity::Parameter(Arc::new(param));
-          let handle = resources.wrap(entity.clone(), name, None);
+    log::debug!(
+      "found {} parameters in {}",
+      params.len(),
+      path.display().to_string().purple().italic()
+    );
 
-          let event = EntityEvent::Loaded { handle, entity };
-          publisher.publish(event);
-        }
+    // sort parameters by name so that they always get registered in the same order
+    let mut params: Vec<_> = params.into_iter().collect();
+    params.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-        Ok(())
-      }
+    let decoded = params
+      .into_iter()
+      .map(|(name, param)| {
+        log::debug!("  found parameter {}: {:?}", name.purple().italic(), param);
+        let entity = Entity::Parameter(Arc::new(param));
+        Decoded::new(name, entity, DecodingMetadata::from_source(path))
+      })
+      .collect();
 
-      Err(err) => Err(err),
-    }
+    Ok(decoded)
   }
 }
 
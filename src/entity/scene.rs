@@ -0,0 +1,571 @@
+//! Scene nodes and the glTF decoder.
+//!
+//! A glTF file is decoded into several entities:
+//!
+//! - Every primitive of every mesh yields a [`Mesh`], named `<path>#meshes/<mesh>/<primitive>`.
+//! - Every material yields a [`Material`], named `<path>#materials/<material>`.
+//! - Every node yields a [`Node`], named `<path>#nodes/<node>`.
+//!
+//! Meshes, materials and nodes are named after their glTF name if they have a unique one, or after their index
+//! otherwise.
+
+use crate::{
+  entity::{
+    decoder::{Decoded, Decoder, DecodingMetadata},
+    material::{AlphaMode, Material, MaterialTexture},
+    mesh::{Mesh, MeshIndex, MeshVertex, VertexAttributes},
+    Entity,
+  },
+  system::resource::ResourceManager,
+};
+use colored::Colorize as _;
+use gltf::{buffer, image, mesh::Mode as GLTFMode};
+use luminance::tess::Mode;
+use std::{
+  collections::HashSet,
+  error, fmt,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+/// A node of a scene.
+///
+/// Nodes reference other entities by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+  /// Translation of the node, relative to its parent.
+  pub translation: [f32; 3],
+
+  /// Rotation of the node, relative to its parent, as a `[x, y, z, w]` quaternion.
+  pub rotation: [f32; 4],
+
+  /// Scale of the node, relative to its parent.
+  pub scale: [f32; 3],
+
+  /// Primitives to render at the node.
+  pub primitives: Vec<NodePrimitive>,
+
+  /// Names of the children nodes.
+  pub children: Vec<String>,
+}
+
+/// A primitive rendered at a [`Node`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodePrimitive {
+  /// Name of the [`Mesh`].
+  pub mesh: String,
+
+  /// Name of the [`Material`] to render the mesh with; the default material is used if absent.
+  pub material: Option<String>,
+}
+
+/// Possible errors that can happen while loading a glTF file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SceneLoadingError {
+  /// The glTF file cannot be parsed.
+  CannotParse { path: PathBuf, reason: String },
+
+  /// The buffers or images of the glTF file cannot be loaded.
+  CannotLoadBuffers { path: PathBuf, reason: String },
+
+  /// A mesh primitive doesn’t have any position.
+  MissingPositions { mesh: String },
+
+  /// A mesh primitive has an index referring to a vertex it doesn’t have.
+  IndexOutOfBounds {
+    mesh: String,
+    index: MeshIndex,
+    vertex_count: usize,
+  },
+}
+
+impl SceneLoadingError {
+  fn cannot_parse(path: impl Into<PathBuf>, reason: impl Into<String>) -> Self {
+    SceneLoadingError::CannotParse {
+      path: path.into(),
+      reason: reason.into(),
+    }
+  }
+
+  fn cannot_load_buffers(path: impl Into<PathBuf>, reason: impl Into<String>) -> Self {
+    SceneLoadingError::CannotLoadBuffers {
+      path: path.into(),
+      reason: reason.into(),
+    }
+  }
+
+  fn missing_positions(mesh: impl Into<String>) -> Self {
+    SceneLoadingError::MissingPositions { mesh: mesh.into() }
+  }
+
+  fn index_out_of_bounds(mesh: impl Into<String>, index: MeshIndex, vertex_count: usize) -> Self {
+    SceneLoadingError::IndexOutOfBounds {
+      mesh: mesh.into(),
+      index,
+      vertex_count,
+    }
+  }
+}
+
+impl fmt::Display for SceneLoadingError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match *self {
+      SceneLoadingError::CannotParse {
+        ref path,
+        ref reason,
+      } => write!(f, "cannot parse {}: {}", path.display(), reason),
+
+      SceneLoadingError::CannotLoadBuffers {
+        ref path,
+        ref reason,
+      } => write!(
+        f,
+        "cannot load buffers or images of {}: {}",
+        path.display(),
+        reason
+      ),
+
+      SceneLoadingError::MissingPositions { ref mesh } => {
+        write!(f, "mesh {} doesn’t have any position", mesh)
+      }
+
+      SceneLoadingError::IndexOutOfBounds {
+        ref mesh,
+        index,
+        vertex_count,
+      } => write!(
+        f,
+        "mesh {} has an index {} out of bounds (only {} vertices)",
+        mesh, index, vertex_count
+      ),
+    }
+  }
+}
+
+impl error::Error for SceneLoadingError {}
+
+/// Load all the entities of a glTF file (either `.gltf` or `.glb`).
+fn load_gltf(path: &Path) -> Result<Vec<Decoded>, SceneLoadingError> {
+  let path_name = path.display().to_string();
+  let parent = path.parent().unwrap_or(path);
+
+  log::debug!(
+    "loading {} {}",
+    "glTF".yellow().italic(),
+    path_name.purple().italic()
+  );
+
+  // images are loaded too, even though only their paths are used, as the importer validates them along the buffers
+  let (document, buffers, _) = gltf::import(path).map_err(|e| match e {
+    gltf::Error::Binary(_) | gltf::Error::Deserialize(_) | gltf::Error::Validation(_) => {
+      SceneLoadingError::cannot_parse(path, e.to_string())
+    }
+    _ => SceneLoadingError::cannot_load_buffers(path, e.to_string()),
+  })?;
+
+  // external buffers are dependencies of the meshes and external images are dependencies of the materials
+  let mut mesh_dmd = DecodingMetadata::from_source(path);
+  for buffer in document.buffers() {
+    if let buffer::Source::Uri(uri) = buffer.source() {
+      if !uri.starts_with("data:") {
+        mesh_dmd.add_dep(uri_path(parent, uri));
+      }
+    }
+  }
+
+  let mut material_dmd = DecodingMetadata::from_source(path);
+  let images: Vec<_> = document
+    .images()
+    .map(|image| match image.source() {
+      image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+        let image_path = uri_path(parent, uri);
+        material_dmd.add_dep(image_path.clone());
+        image_path.display().to_string()
+      }
+
+      _ => format!("{}#images/{}", path_name, image.index()),
+    })
+    .collect();
+
+  let mesh_names = unique_names(document.meshes().map(|mesh| mesh.name()));
+  let material_names = unique_names(document.materials().map(|material| material.name()));
+  let node_names = unique_names(document.nodes().map(|node| node.name()));
+  let mut decoded = Vec::new();
+
+  for mesh in document.meshes() {
+    for primitive in mesh.primitives() {
+      let name = format!(
+        "{}#meshes/{}/{}",
+        path_name,
+        mesh_names[mesh.index()],
+        primitive.index()
+      );
+      let decoded_mesh = decode_primitive(&primitive, &buffers, &name)?;
+
+      decoded.push(Decoded::new(
+        name,
+        Entity::Mesh(Arc::new(decoded_mesh)),
+        mesh_dmd.clone(),
+      ));
+    }
+  }
+
+  // the default material (without index) is never part of the materials of the document
+  for material in document.materials() {
+    let name = format!(
+      "{}#materials/{}",
+      path_name,
+      material_names[material.index().unwrap_or(0)]
+    );
+
+    decoded.push(Decoded::new(
+      name,
+      Entity::Material(Arc::new(decode_material(&material, &images))),
+      material_dmd.clone(),
+    ));
+  }
+
+  for node in document.nodes() {
+    let name = format!("{}#nodes/{}", path_name, node_names[node.index()]);
+    let (translation, rotation, scale) = node.transform().decomposed();
+    let primitives = node
+      .mesh()
+      .map(|mesh| {
+        mesh
+          .primitives()
+          .map(|primitive| NodePrimitive {
+            mesh: format!(
+              "{}#meshes/{}/{}",
+              path_name,
+              mesh_names[mesh.index()],
+              primitive.index()
+            ),
+            material: primitive
+              .material()
+              .index()
+              .map(|index| format!("{}#materials/{}", path_name, material_names[index])),
+          })
+          .collect()
+      })
+      .unwrap_or_default();
+    let children = node
+      .children()
+      .map(|child| format!("{}#nodes/{}", path_name, node_names[child.index()]))
+      .collect();
+
+    let decoded_node = Node {
+      translation,
+      rotation,
+      scale,
+      primitives,
+      children,
+    };
+
+    decoded.push(Decoded::new(
+      name,
+      Entity::Node(Arc::new(decoded_node)),
+      DecodingMetadata::from_source(path),
+    ));
+  }
+
+  Ok(decoded)
+}
+
+/// Path of a relative URI of a glTF file; URIs are percent-encoded, so `my%20tex.png` is `my tex.png`.
+fn uri_path(parent: &Path, uri: &str) -> PathBuf {
+  let bytes = uri.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      }
+
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+
+  parent.join(String::from_utf8_lossy(&decoded).as_ref())
+}
+
+/// Name items after their own name if it’s unique, or after their index otherwise.
+fn unique_names<'a>(names: impl Iterator<Item = Option<&'a str>>) -> Vec<String> {
+  let names: Vec<_> = names.collect();
+  let mut seen = HashSet::new();
+  let duplicated: HashSet<_> = names
+    .iter()
+    .flatten()
+    .filter(|name| !seen.insert(*name))
+    .collect();
+
+  names
+    .iter()
+    .enumerate()
+    .map(|(index, name)| match name {
+      Some(name) if !name.is_empty() && !duplicated.contains(name) => name.to_string(),
+      _ => index.to_string(),
+    })
+    .collect()
+}
+
+/// Decode a mesh primitive into a [`Mesh`].
+///
+/// Strips, fans and loops are turned into lists. Missing normals are generated flat, as required by glTF, and missing
+/// tangents are generated if texture coordinates are available.
+fn decode_primitive(
+  primitive: &gltf::Primitive,
+  buffers: &[buffer::Data],
+  name: &str,
+) -> Result<Mesh, SceneLoadingError> {
+  let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+  let positions: Vec<[f32; 3]> = reader
+    .read_positions()
+    .ok_or_else(|| SceneLoadingError::missing_positions(name))?
+    .collect();
+  let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+  let uvs: Option<Vec<[f32; 2]>> = reader
+    .read_tex_coords(0)
+    .map(|uvs| uvs.into_f32().collect());
+  let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().map(Iterator::collect);
+  let colors: Option<Vec<[f32; 4]>> = reader
+    .read_colors(0)
+    .map(|colors| colors.into_rgba_f32().collect());
+  let indices: Vec<MeshIndex> = reader
+    .read_indices()
+    .map(|indices| indices.into_u32().collect())
+    .unwrap_or_else(|| (0..positions.len() as MeshIndex).collect());
+
+  // malformed files can have indices past the vertices, which every step below relies on
+  if let Some(&index) = indices
+    .iter()
+    .find(|&&index| index as usize >= positions.len())
+  {
+    return Err(SceneLoadingError::index_out_of_bounds(
+      name,
+      index,
+      positions.len(),
+    ));
+  }
+
+  let mut attributes = VertexAttributes {
+    uv: uvs.is_some(),
+    tangent: tangents.is_some(),
+    color: colors.is_some(),
+  };
+
+  let mut vertices: Vec<_> = positions
+    .iter()
+    .enumerate()
+    .map(|(i, &pos)| {
+      let nor = normals
+        .as_ref()
+        .and_then(|normals| normals.get(i).copied())
+        .unwrap_or(Mesh::DEFAULT_NORMAL);
+      let mut vertex = MeshVertex::new(pos, nor);
+
+      if let Some(uv) = uvs.as_ref().and_then(|uvs| uvs.get(i)) {
+        vertex = vertex.with_uv(*uv);
+      }
+
+      if let Some(tan) = tangents.as_ref().and_then(|tangents| tangents.get(i)) {
+        vertex = vertex.with_tangent(*tan);
+      }
+
+      if let Some(col) = colors.as_ref().and_then(|colors| colors.get(i)) {
+        vertex = vertex.with_color(*col);
+      }
+
+      vertex
+    })
+    .collect();
+
+  let (mode, mut indices) = match primitive.mode() {
+    GLTFMode::Points => (Mode::Point, indices),
+    GLTFMode::Lines => (Mode::Line, indices),
+    GLTFMode::LineStrip => (Mode::LineStrip, indices),
+
+    GLTFMode::LineLoop => {
+      let mut indices = indices;
+      if let Some(&first) = indices.first() {
+        indices.push(first);
+      }
+
+      (Mode::LineStrip, indices)
+    }
+
+    GLTFMode::Triangles => (Mode::Triangle, indices),
+
+    GLTFMode::TriangleStrip => {
+      let list = (0..indices.len().saturating_sub(2))
+        .flat_map(|i| {
+          // every other triangle of a strip has its winding reversed
+          if i % 2 == 0 {
+            vec![indices[i], indices[i + 1], indices[i + 2]]
+          } else {
+            vec![indices[i + 1], indices[i], indices[i + 2]]
+          }
+        })
+        .collect();
+
+      (Mode::Triangle, list)
+    }
+
+    GLTFMode::TriangleFan => {
+      let list = (1..indices.len().saturating_sub(1))
+        .flat_map(|i| vec![indices[0], indices[i], indices[i + 1]])
+        .collect();
+
+      (Mode::Triangle, list)
+    }
+  };
+
+  if let Mode::Triangle = mode {
+    if normals.is_none() {
+      // flat normals; vertices can’t be shared between faces anymore
+      let triangles: Vec<_> = indices
+        .chunks_exact(3)
+        .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+        .collect();
+      let flat_normals = Mesh::generate_normals(&positions, &triangles, |_, _, _| false);
+
+      vertices = triangles
+        .iter()
+        .zip(flat_normals)
+        .flat_map(|(triangle, normals)| {
+          let vertices = &vertices;
+          triangle.iter().zip(normals.to_vec()).map(move |(&i, nor)| {
+            let vertex = vertices[i];
+            MeshVertex::new(vertex.pos(), nor)
+              .with_uv(vertex.uv())
+              .with_tangent(vertex.tangent())
+              .with_color(vertex.color())
+          })
+        })
+        .collect();
+      indices = (0..vertices.len() as MeshIndex).collect();
+    }
+
+    if tangents.is_none() && uvs.is_some() {
+      Mesh::generate_tangents(&mut vertices, &indices);
+      attributes.tangent = true;
+    }
+  }
+
+  Ok(Mesh::new(vertices, indices, mode, attributes))
+}
+
+/// Decode a glTF material into a [`Material`].
+fn decode_material(material: &gltf::Material, images: &[String]) -> Material {
+  let texture = |texture: gltf::Texture, tex_coord| MaterialTexture {
+    image: images[texture.source().index()].clone(),
+    tex_coord,
+  };
+  let pbr = material.pbr_metallic_roughness();
+
+  let alpha_mode = match material.alpha_mode() {
+    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+    gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+      cutoff: material.alpha_cutoff(),
+    },
+    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+  };
+
+  Material {
+    base_color: pbr.base_color_factor(),
+    base_color_texture: pbr
+      .base_color_texture()
+      .map(|info| texture(info.texture(), info.tex_coord())),
+    metallic: pbr.metallic_factor(),
+    roughness: pbr.roughness_factor(),
+    metallic_roughness_texture: pbr
+      .metallic_roughness_texture()
+      .map(|info| texture(info.texture(), info.tex_coord())),
+    normal_texture: material
+      .normal_texture()
+      .map(|info| texture(info.texture(), info.tex_coord())),
+    occlusion_texture: material
+      .occlusion_texture()
+      .map(|info| texture(info.texture(), info.tex_coord())),
+    emissive: material.emissive_factor(),
+    emissive_texture: material
+      .emissive_texture()
+      .map(|info| texture(info.texture(), info.tex_coord())),
+    alpha_mode,
+    double_sided: material.double_sided(),
+  }
+}
+
+/// The glTF encoding format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct GLTFDecoder;
+
+impl Decoder for GLTFDecoder {
+  const EXT: &'static str = "gltf";
+
+  const SUB_EXT: &'static str = "";
+
+  type Err = SceneLoadingError;
+
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    load_gltf(path.as_ref())
+  }
+}
+
+/// The binary glTF encoding format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct GLBDecoder;
+
+impl Decoder for GLBDecoder {
+  const EXT: &'static str = "glb";
+
+  const SUB_EXT: &'static str = "";
+
+  type Err = SceneLoadingError;
+
+  fn load_from_file(
+    _: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    load_gltf(path.as_ref())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn names_fall_back_to_indices() {
+    let names = unique_names(vec![Some("a"), None, Some("b"), Some("a"), Some("")].into_iter());
+
+    assert_eq!(names, vec!["0", "1", "b", "3", "4"]);
+  }
+
+  #[test]
+  fn uri_paths_are_percent_decoded() {
+    let parent = Path::new("assets");
+
+    assert_eq!(
+      uri_path(parent, "my%20tex%2Epng"),
+      parent.join("my tex.png")
+    );
+    assert_eq!(uri_path(parent, "caf%C3%A9.bin"), parent.join("café.bin"));
+    assert_eq!(uri_path(parent, "100%.bin"), parent.join("100%.bin"));
+    assert_eq!(uri_path(parent, "a%zz%4"), parent.join("a%zz%4"));
+  }
+}
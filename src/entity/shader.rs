@@ -2,15 +2,28 @@
 
 use crate::{
   entity::{
-    decoder::{Decoder, DecodingMetadata},
-    Entity, EntityEvent,
+    decoder::{Decoded, Decoder, DecodingMetadata},
+    shader::validation::ValidationError,
+    Entity,
   },
-  system::{resource::ResourceManager, Publisher},
+  system::resource::ResourceManager,
 };
 use colored::Colorize as _;
 use glsl::{parser::Parse as _, parser::ParseError, syntax::ShaderStage};
 use serde::{Deserialize, Serialize};
-use std::{error, fmt, fs, io, path::Path, path::PathBuf, sync::Arc};
+use std::{
+  collections::{BTreeMap, HashSet},
+  error, fmt,
+  fmt::Write as _,
+  fs, io, iter,
+  path::Path,
+  path::PathBuf,
+  sync::Arc,
+};
+
+mod include;
+mod stages;
+pub mod validation;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Shader {
@@ -37,8 +50,18 @@ impl ShaderData {
       ast,
     }
   }
+
+  /// Inject `#define`s into a GLSL source and parse it.
+  pub fn with_defines(src: &str, defines: &ShaderDefines) -> Result<Self, ShaderError> {
+    let raw = inject_defines(src, defines);
+    let ast = ShaderStage::parse(&raw)?;
+    Ok(Self::new(raw, ast))
+  }
 }
 
+/// Preprocessor defines, mapping names to values; an empty value defines a name without any value.
+pub type ShaderDefines = BTreeMap<String, String>;
+
 /// Paths for each shader stages.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct ShaderInfo {
@@ -53,16 +76,27 @@ pub struct ShaderInfo {
   geo_shader: PathBuf,
   #[serde(rename = "fragment-shader")]
   frag_shader: PathBuf,
+  /// Named variants of the shader, each with the defines to inject into all its stages.
+  #[serde(default)]
+  variants: BTreeMap<String, ShaderDefines>,
 }
 
 impl Shader {
-  /// Load a shader from a given `path`.
+  /// Load a shader and its variants from a given `path`.
   ///
   /// The `prefix` argument allows to automatically insert a prefix path if `path` starts with `'/'`.
+  ///
+  /// `#include` directives are resolved in every stage. The first returned shader is the one without any define; it’s
+  /// followed by one shader per variant, named `name:variant`. Every shader is validated with [`Shader::validate`];
+  /// validation issues are only logged as warnings.
+  ///
+  /// The paths of all the shader stages the shader depends on, along with the paths of all the files they include, are
+  /// added to `dmd` as soon as they’re known, so that they’re available even if loading fails.
   pub fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
+    resources: &ResourceManager<Entity>,
     path: impl AsRef<Path>,
-  ) -> Result<(Self, ShaderInfo), ShaderError> {
+    dmd: &mut DecodingMetadata,
+  ) -> Result<Vec<Self>, ShaderError> {
     let path = path.as_ref();
     let parent = path.parent().unwrap_or(path);
 
@@ -75,68 +109,203 @@ impl Shader {
     let content = fs::read_to_string(path)?;
     let shader_info: ShaderInfo = serde_json::from_str(&content)?;
 
-    // vertex shader
-    let vert_path = resources.resource_to_relative_path(parent, &shader_info.vert_shader);
-    let vert_shader = if vert_path.is_file() {
-      let src = fs::read_to_string(&vert_path)?;
-      let ast = ShaderStage::parse(&src)?;
-      ShaderData::new(src, ast)
-    } else {
-      return Err(ShaderError::MissingVertexShader(vert_path));
-    };
+    let vert_src =
+      read_stage(resources, parent, &shader_info.vert_shader, dmd)?.ok_or_else(|| {
+        ShaderError::MissingVertexShader(
+          resources.resource_to_relative_path(parent, &shader_info.vert_shader),
+        )
+      })?;
+    let tess_ctrl_src = read_stage(resources, parent, &shader_info.tess_ctrl_shader, dmd)?;
+    let tess_eval_src = read_stage(resources, parent, &shader_info.tess_eval_shader, dmd)?;
+    let geo_src = read_stage(resources, parent, &shader_info.geo_shader, dmd)?;
+    let frag_src =
+      read_stage(resources, parent, &shader_info.frag_shader, dmd)?.ok_or_else(|| {
+        ShaderError::MissingFragmentShader(
+          resources.resource_to_relative_path(parent, &shader_info.frag_shader),
+        )
+      })?;
+
+    // the shader without any define, then one shader per variant
+    let no_defines = ShaderDefines::new();
+    let variants = iter::once((shader_info.name.clone(), &no_defines)).chain(
+      shader_info
+        .variants
+        .iter()
+        .map(|(variant, defines)| (format!("{}:{}", shader_info.name, variant), defines)),
+    );
 
-    // tessellation control shader
-    let tess_ctrl_path = resources.resource_to_relative_path(parent, &shader_info.tess_ctrl_shader);
-    let tess_ctrl_shader = if tess_ctrl_path.is_file() {
-      let src = fs::read_to_string(tess_ctrl_path)?;
-      let ast = ShaderStage::parse(&src)?;
+    let shaders = variants
+      .map(|(name, defines)| -> Result<Shader, ShaderError> {
+        let stage = |src: &str| ShaderData::with_defines(src, defines);
+        let optional_stage = |src: &Option<String>| src.as_deref().map(stage).transpose();
+
+        let shader = Shader {
+          name,
+          vert_shader: stage(&vert_src)?,
+          tess_ctrl_shader: optional_stage(&tess_ctrl_src)?,
+          tess_eval_shader: optional_stage(&tess_eval_src)?,
+          geo_shader: optional_stage(&geo_src)?,
+          frag_shader: stage(&frag_src)?,
+        };
+
+        shader.warn_invalid();
+        Ok(shader)
+      })
+      .collect::<Result<_, _>>()?;
+
+    Ok(shaders)
+  }
 
-      Some(ShaderData::new(src, ast))
-    } else {
-      None
-    };
+  /// Load a shader holding all its stages in a single `.glsl` file, split by `#pragma stage` directives.
+  ///
+  /// The shader is named after `path`. `#include` directives are resolved in every stage. If the file doesn’t have any
+  /// `#pragma stage` directive, it’s not a shader and `None` is returned. Validation issues are only logged as warnings.
+  ///
+  /// The paths of all the included files are added to `dmd` as soon as they’re known, so that they’re available even
+  /// if loading fails.
+  pub fn load_from_glsl_file(
+    resources: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+    dmd: &mut DecodingMetadata,
+  ) -> Result<Option<Self>, ShaderError> {
+    let path = path.as_ref();
 
-    // tessellation evaluation shader
-    let tess_eval_path = resources.resource_to_relative_path(parent, &shader_info.tess_eval_shader);
-    let tess_eval_shader = if tess_eval_path.is_file() {
-      let src = fs::read_to_string(tess_eval_path)?;
-      let ast = ShaderStage::parse(&src)?;
-      Some(ShaderData::new(src, ast))
-    } else {
-      None
-    };
+    log::debug!(
+      "loading {} {}",
+      "single-file shader".yellow().italic(),
+      path.display().to_string().purple().italic()
+    );
 
-    // geometry shader
-    let geo_path = resources.resource_to_relative_path(parent, &shader_info.geo_shader);
-    let geo_shader = if geo_path.is_file() {
-      let src = fs::read_to_string(geo_path)?;
-      let ast = ShaderStage::parse(&src)?;
-      Some(ShaderData::new(src, ast))
-    } else {
-      None
+    let content = fs::read_to_string(path)?;
+    let stages = match stages::split_stages(path, &content)? {
+      Some(stages) => stages,
+      None => {
+        log::debug!(
+          "{} doesn’t have any stage; ignoring",
+          path.display().to_string().purple().italic()
+        );
+        return Ok(None);
+      }
     };
 
-    // fragment shader
-    let frag_path = resources.resource_to_relative_path(parent, &shader_info.frag_shader);
-    let frag_shader = if frag_path.is_file() {
-      let src = fs::read_to_string(&frag_path)?;
-      let ast = ShaderStage::parse(&src)?;
-      ShaderData::new(src, ast)
-    } else {
-      return Err(ShaderError::MissingFragmentShader(frag_path));
+    let mut stage = |src: Option<String>| {
+      src
+        .map(|src| -> Result<_, ShaderError> {
+          let src = include::resolve_includes(resources, path, &src, dmd)?;
+          let ast = ShaderStage::parse(&src)?;
+          Ok(ShaderData::new(src, ast))
+        })
+        .transpose()
     };
 
+    let vert_shader =
+      stage(stages.vert)?.ok_or_else(|| ShaderError::MissingVertexShader(path.to_owned()))?;
+    let tess_ctrl_shader = stage(stages.tess_ctrl)?;
+    let tess_eval_shader = stage(stages.tess_eval)?;
+    let geo_shader = stage(stages.geo)?;
+    let frag_shader =
+      stage(stages.frag)?.ok_or_else(|| ShaderError::MissingFragmentShader(path.to_owned()))?;
+
     let shader = Shader {
-      name: shader_info.name.clone(),
-      vert_shader: vert_shader.clone(),
-      tess_ctrl_shader: tess_ctrl_shader.clone(),
-      tess_eval_shader: tess_eval_shader.clone(),
-      geo_shader: geo_shader.clone(),
-      frag_shader: frag_shader.clone(),
+      name: path.display().to_string(),
+      vert_shader,
+      tess_ctrl_shader,
+      tess_eval_shader,
+      geo_shader,
+      frag_shader,
     };
 
-    Ok((shader, shader_info))
+    shader.warn_invalid();
+    Ok(Some(shader))
+  }
+
+  /// Validate the shader without compiling it; see [`validation`] for the checks performed.
+  pub fn validate(&self) -> Result<(), ShaderError> {
+    validation::validate(self).map_err(|errors| ShaderError::Invalid {
+      name: self.name.clone(),
+      errors,
+    })
+  }
+
+  /// Validate the shader, only logging the issues found.
+  ///
+  /// Validation doesn’t know which preprocessor branches are active, so a valid shader can still be reported; the
+  /// GPU compiler has the final say.
+  fn warn_invalid(&self) {
+    if let Err(err) = self.validate() {
+      log::warn!("{}", err);
+    }
+  }
+}
+
+/// Read a shader stage which path, relative to `parent`, is `stage`, and resolve its `#include` directives.
+///
+/// The stage is added to `dmd` before being read, so that creating or fixing it reloads the shader. Return `None` if no
+/// stage is set or if the stage doesn’t exist.
+fn read_stage(
+  resources: &ResourceManager<Entity>,
+  parent: &Path,
+  stage: &Path,
+  dmd: &mut DecodingMetadata,
+) -> Result<Option<String>, ShaderError> {
+  if stage.as_os_str().is_empty() {
+    return Ok(None);
+  }
+
+  let stage_path = resources.resource_to_relative_path(parent, stage);
+  let _ = dmd.add_dep(stage_path.clone());
+
+  if stage_path.is_file() {
+    include::read_stage(resources, &stage_path, dmd).map(Some)
+  } else {
+    Ok(None)
+  }
+}
+
+/// Inject `#define`s into a GLSL source, right after its `#version` directive if any.
+///
+/// A `#line` directive follows the defines so that line numbers still match the original source.
+fn inject_defines(src: &str, defines: &ShaderDefines) -> String {
+  if defines.is_empty() {
+    return src.to_owned();
+  }
+
+  let lines: Vec<_> = src.lines().collect();
+  let insert_at = lines
+    .iter()
+    .position(|line| {
+      line
+        .trim_start()
+        .strip_prefix('#')
+        .map(|directive| directive.trim_start().starts_with("version"))
+        .unwrap_or(false)
+    })
+    .map(|version_line| version_line + 1)
+    .unwrap_or(0);
+
+  let mut output = String::with_capacity(src.len());
+
+  for line in &lines[..insert_at] {
+    output.push_str(line);
+    output.push('\n');
+  }
+
+  for (name, value) in defines {
+    if value.is_empty() {
+      let _ = writeln!(output, "#define {}", name);
+    } else {
+      let _ = writeln!(output, "#define {} {}", name, value);
+    }
+  }
+
+  let _ = writeln!(output, "#line {} 0", insert_at + 1);
+
+  for line in &lines[insert_at..] {
+    output.push_str(line);
+    output.push('\n');
   }
+
+  output
 }
 
 #[derive(Debug)]
@@ -146,6 +315,29 @@ pub enum ShaderError {
   GLSLError(ParseError),
   MissingVertexShader(PathBuf),
   MissingFragmentShader(PathBuf),
+  InvalidInclude {
+    path: PathBuf,
+    line: usize,
+  },
+  IncludeNotFound {
+    path: PathBuf,
+    included_from: PathBuf,
+  },
+  IncludeCycle(Vec<PathBuf>),
+  UnknownStage {
+    path: PathBuf,
+    line: usize,
+    stage: String,
+  },
+  DuplicateStage {
+    path: PathBuf,
+    line: usize,
+    stage: String,
+  },
+  Invalid {
+    name: String,
+    errors: Vec<ValidationError>,
+  },
 }
 
 impl From<io::Error> for ShaderError {
@@ -178,12 +370,92 @@ impl fmt::Display for ShaderError {
       ShaderError::MissingFragmentShader(ref path) => {
         write!(f, "missing fragment shader at path {}", path.display())
       }
+      ShaderError::InvalidInclude { ref path, line } => {
+        write!(
+          f,
+          "invalid #include directive at {}:{}",
+          path.display(),
+          line
+        )
+      }
+      ShaderError::IncludeNotFound {
+        ref path,
+        ref included_from,
+      } => write!(
+        f,
+        "cannot find {} included from {}",
+        path.display(),
+        included_from.display()
+      ),
+      ShaderError::IncludeCycle(ref cycle) => {
+        let cycle: Vec<_> = cycle
+          .iter()
+          .map(|path| path.display().to_string())
+          .collect();
+        write!(f, "include cycle: {}", cycle.join(" -> "))
+      }
+      ShaderError::UnknownStage {
+        ref path,
+        line,
+        ref stage,
+      } => write!(
+        f,
+        "unknown stage {:?} at {}:{}",
+        stage,
+        path.display(),
+        line
+      ),
+      ShaderError::DuplicateStage {
+        ref path,
+        line,
+        ref stage,
+      } => write!(
+        f,
+        "duplicate {} stage at {}:{}",
+        stage,
+        path.display(),
+        line
+      ),
+      ShaderError::Invalid {
+        ref name,
+        ref errors,
+      } => {
+        let errors: Vec<_> = errors.iter().map(ValidationError::to_string).collect();
+        write!(f, "invalid shader {}: {}", name, errors.join("; "))
+      }
     }
   }
 }
 
 impl error::Error for ShaderError {}
 
+/// Error of the shader decoders.
+///
+/// Along with the [`ShaderError`], it carries the paths the shader depended on up to the failure, so that fixing any
+/// of them reloads the shader.
+#[derive(Debug)]
+pub struct ShaderDecodingError {
+  pub err: ShaderError,
+  pub path_deps: HashSet<PathBuf>,
+}
+
+impl ShaderDecodingError {
+  fn new(err: ShaderError, dmd: DecodingMetadata) -> Self {
+    Self {
+      err,
+      path_deps: dmd.path_deps,
+    }
+  }
+}
+
+impl fmt::Display for ShaderDecodingError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    self.err.fmt(f)
+  }
+}
+
+impl error::Error for ShaderDecodingError {}
+
 #[derive(Debug)]
 pub struct JSONShaderDecoder;
 
@@ -192,46 +464,198 @@ impl Decoder for JSONShaderDecoder {
 
   const SUB_EXT: &'static str = "shd";
 
-  type Err = ShaderError;
+  type Err = ShaderDecodingError;
 
   fn load_from_file(
-    resources: &mut ResourceManager<Entity>,
-    publisher: &mut impl Publisher<EntityEvent>,
+    resources: &ResourceManager<Entity>,
     path: impl AsRef<Path>,
-  ) -> Result<(), Self::Err> {
+  ) -> Result<Vec<Decoded>, Self::Err> {
     let path = path.as_ref();
+    let mut dmd = DecodingMetadata::from_source(path);
+    let shaders = match Shader::load_from_file(resources, path, &mut dmd) {
+      Ok(shaders) => shaders,
+      Err(err) => return Err(ShaderDecodingError::new(err, dmd)),
+    };
 
-    match Shader::load_from_file(resources, path) {
-      Ok((shader, info)) => {
-        let entity = Entity::Shader(Arc::new(shader));
+    let decoded = shaders
+      .into_iter()
+      .map(|shader| {
+        let name = shader.name.clone();
+        Decoded::new(name, Entity::Shader(Arc::new(shader)), dmd.clone())
+      })
+      .collect();
 
-        // create the dependency tracking
-        let mut deps = vec![info.vert_shader, info.frag_shader];
+    Ok(decoded)
+  }
 
-        if info.tess_ctrl_shader.is_file() {
-          deps.push(info.tess_ctrl_shader);
-        }
+  fn error_path_deps(err: &Self::Err) -> HashSet<PathBuf> {
+    err.path_deps.clone()
+  }
+}
 
-        if info.tess_eval_shader.is_file() {
-          deps.push(info.tess_eval_shader);
-        }
+/// Single-file shaders, holding all their stages in one `.glsl` file.
+///
+/// `.glsl` files without any `#pragma stage` directive, such as files meant to be included, yield no entity.
+#[derive(Debug)]
+pub struct GLSLShaderDecoder;
 
-        if info.geo_shader.is_file() {
-          deps.push(info.geo_shader);
-        }
+impl Decoder for GLSLShaderDecoder {
+  const EXT: &'static str = "glsl";
 
-        let handle = resources.wrap(entity.clone(), info.name, DecodingMetadata::with_deps(deps));
+  const SUB_EXT: &'static str = "";
 
-        let path = path.display().to_string().purple().italic();
-        log::info!("{} shader {} at {}", "loaded".green().bold(), handle, path);
+  type Err = ShaderDecodingError;
 
-        let event = EntityEvent::Loaded { handle, entity };
-        publisher.publish(event);
+  fn load_from_file(
+    resources: &ResourceManager<Entity>,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Decoded>, Self::Err> {
+    let path = path.as_ref();
+    let mut dmd = DecodingMetadata::from_source(path);
+    let shader = match Shader::load_from_glsl_file(resources, path, &mut dmd) {
+      Ok(shader) => shader,
+      Err(err) => return Err(ShaderDecodingError::new(err, dmd)),
+    };
 
-        Ok(())
-      }
+    let decoded = shader
+      .map(|shader| {
+        let name = shader.name.clone();
+        Decoded::new(name, Entity::Shader(Arc::new(shader)), dmd)
+      })
+      .into_iter()
+      .collect();
 
-      Err(err) => Err(err),
-    }
+    Ok(decoded)
+  }
+
+  fn error_path_deps(err: &Self::Err) -> HashSet<PathBuf> {
+    err.path_deps.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TempDir;
+
+  #[test]
+  fn defines_follow_version() {
+    let mut defines = ShaderDefines::new();
+    defines.insert("FOG".to_owned(), String::new());
+    defines.insert("SHADOW_SAMPLES".to_owned(), "4".to_owned());
+
+    assert_eq!(
+      inject_defines("#version 330 core\n\nvoid main() {}\n", &defines),
+      "#version 330 core\n#define FOG\n#define SHADOW_SAMPLES 4\n#line 2 0\n\nvoid main() {}\n"
+    );
+    assert_eq!(
+      inject_defines("void main() {}\n", &defines),
+      "#define FOG\n#define SHADOW_SAMPLES 4\n#line 1 0\nvoid main() {}\n"
+    );
+    assert_eq!(
+      inject_defines("void main() {}\n", &ShaderDefines::new()),
+      "void main() {}\n"
+    );
+  }
+
+  #[test]
+  fn variants() {
+    let root = TempDir::new("variants");
+
+    fs::write(
+      root.join("lit.shd.json"),
+      r#"{
+        "name": "lit",
+        "vertex-shader": "lit.vert",
+        "fragment-shader": "lit.frag",
+        "variants": { "fog": { "FOG": "" }, "shadows": { "SHADOWS": "", "SHADOW_SAMPLES": "4" } }
+      }"#,
+    )
+    .unwrap();
+    fs::write(
+      root.join("lit.vert"),
+      "#version 330 core\nvoid main() {\n  gl_Position = vec4(0.);\n}\n",
+    )
+    .unwrap();
+    fs::write(
+      root.join("lit.frag"),
+      "#version 330 core\nout vec4 color;\n#ifdef FOG\nfloat fog = 1.;\n#endif\nvoid main() {\n  color = vec4(1.);\n}\n",
+    )
+    .unwrap();
+
+    let resources = ResourceManager::new(root.to_path_buf());
+    let mut dmd = DecodingMetadata::from_source(root.join("lit.shd.json"));
+    let shaders = Shader::load_from_file(&resources, root.join("lit.shd.json"), &mut dmd).unwrap();
+    let names: Vec<_> = shaders.iter().map(|shader| shader.name.as_str()).collect();
+
+    assert_eq!(names, vec!["lit", "lit:fog", "lit:shadows"]);
+    assert!(!shaders[0].frag_shader.raw.contains("#define"));
+    assert!(shaders[1].frag_shader.raw.contains("#define FOG\n"));
+    assert!(shaders[2]
+      .vert_shader
+      .raw
+      .contains("#define SHADOW_SAMPLES 4\n"));
+    assert!(dmd.path_deps.contains(&root.join("lit.frag")));
+  }
+
+  #[test]
+  fn failed_shaders_keep_deps() {
+    let root = TempDir::new("failed-deps");
+
+    fs::write(
+      root.join("broken.shd.json"),
+      r#"{ "name": "broken", "vertex-shader": "broken.vert", "fragment-shader": "broken.frag" }"#,
+    )
+    .unwrap();
+    fs::write(root.join("broken.vert"), "void main() {\n").unwrap();
+    fs::write(
+      root.join("broken.frag"),
+      "#include \"missing.glsl\"\nvoid main() {}\n",
+    )
+    .unwrap();
+
+    let resources = ResourceManager::new(root.to_path_buf());
+    let err =
+      JSONShaderDecoder::load_from_file(&resources, root.join("broken.shd.json")).unwrap_err();
+
+    assert!(matches!(err.err, ShaderError::IncludeNotFound { .. }));
+    assert!(err.path_deps.contains(&root.join("broken.vert")));
+    assert!(err.path_deps.contains(&root.join("broken.frag")));
+    assert!(err.path_deps.contains(&root.join("missing.glsl")));
+  }
+
+  #[test]
+  fn single_file() {
+    let root = TempDir::new("single-file");
+
+    fs::write(
+      root.join("color.glsl"),
+      "vec4 white() { return vec4(1.); }\n",
+    )
+    .unwrap();
+    fs::write(
+      root.join("effect.glsl"),
+      "#version 330 core\n\n#pragma stage vertex\nvoid main() {\n  gl_Position = vec4(0.);\n}\n\n#pragma stage fragment\n#include \"color.glsl\"\nout vec4 color;\nvoid main() {\n  color = white();\n}\n",
+    )
+    .unwrap();
+
+    let resources = ResourceManager::new(root.to_path_buf());
+    let mut dmd = DecodingMetadata::from_source(root.join("effect.glsl"));
+    let shader = Shader::load_from_glsl_file(&resources, root.join("effect.glsl"), &mut dmd)
+      .unwrap()
+      .unwrap();
+
+    assert_eq!(shader.name, root.join("effect.glsl").display().to_string());
+    assert!(!shader.vert_shader.raw.contains("color"));
+    assert!(shader.frag_shader.raw.contains("vec4 white()"));
+    assert!(shader.tess_ctrl_shader.is_none());
+    assert!(dmd.path_deps.contains(&root.join("color.glsl")));
+
+    // files without stages are not shaders
+    assert!(
+      Shader::load_from_glsl_file(&resources, root.join("color.glsl"), &mut dmd)
+        .unwrap()
+        .is_none()
+    );
   }
 }
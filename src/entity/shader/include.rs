@@ -0,0 +1,282 @@
+//! GLSL `#include` directives.
+//!
+//! A shader stage can include other GLSL files with `#include "path"`. Included paths are resolved like the paths of
+//! the stages: relative to the including file, or relative to the root directory if they start with `/`. Included files
+//! can include other files themselves.
+//!
+//! A file is included at most once per stage, so shared files don’t need include guards. Include cycles are errors.
+//! `#line` directives are inserted around included files: the stage is source string `0`, and included files are
+//! numbered from `1` in the order they’re included.
+
+use crate::{
+  entity::{decoder::DecodingMetadata, shader::ShaderError, Entity},
+  system::resource::ResourceManager,
+};
+use std::{
+  collections::HashSet,
+  fmt::Write as _,
+  fs,
+  path::{Component, Path, PathBuf},
+};
+
+/// Read a shader stage and resolve its `#include` directives.
+///
+/// All the included files are registered as dependencies in `dmd`, even if resolving the includes fails.
+pub fn read_stage(
+  resources: &ResourceManager<Entity>,
+  path: &Path,
+  dmd: &mut DecodingMetadata,
+) -> Result<String, ShaderError> {
+  let content = fs::read_to_string(path)?;
+  resolve_includes(resources, path, &content, dmd)
+}
+
+/// Resolve the `#include` directives of a shader stage which source was read from `path`.
+///
+/// All the included files are registered as dependencies in `dmd`, even if resolving the includes fails.
+pub fn resolve_includes(
+  resources: &ResourceManager<Entity>,
+  path: &Path,
+  src: &str,
+  dmd: &mut DecodingMetadata,
+) -> Result<String, ShaderError> {
+  let mut includer = Includer {
+    resources,
+    dmd,
+    stack: Vec::new(),
+    included: HashSet::new(),
+  };
+
+  includer.expand(&normalize(path), src, 0)
+}
+
+/// State of the resolution of the includes of a stage.
+struct Includer<'a> {
+  resources: &'a ResourceManager<Entity>,
+  dmd: &'a mut DecodingMetadata,
+  /// Files being read, from the stage down to the file currently read.
+  stack: Vec<PathBuf>,
+  /// Files already included.
+  included: HashSet<PathBuf>,
+}
+
+impl<'a> Includer<'a> {
+  /// Expand the includes of `content`, read from `path`.
+  fn expand(
+    &mut self,
+    path: &Path,
+    content: &str,
+    source_string: usize,
+  ) -> Result<String, ShaderError> {
+    let parent = path.parent().unwrap_or(path);
+    let mut output = String::with_capacity(content.len());
+
+    self.stack.push(path.to_owned());
+
+    for (line_index, line) in content.lines().enumerate() {
+      let included = match parse_include(line) {
+        None => {
+          output.push_str(line);
+          output.push('\n');
+          continue;
+        }
+
+        Some(Ok(included)) => included,
+
+        Some(Err(())) => {
+          return Err(ShaderError::InvalidInclude {
+            path: path.to_owned(),
+            line: line_index + 1,
+          })
+        }
+      };
+
+      let included_path = normalize(&self.resources.resource_to_relative_path(parent, included));
+
+      // depend on the included file before checking it, so that creating or fixing it reloads the shader
+      let _ = self.dmd.add_dep(included_path.clone());
+
+      if self.stack.contains(&included_path) {
+        let mut cycle = self.stack.clone();
+        cycle.push(included_path);
+        return Err(ShaderError::IncludeCycle(cycle));
+      }
+
+      if !included_path.is_file() {
+        return Err(ShaderError::IncludeNotFound {
+          path: included_path,
+          included_from: path.to_owned(),
+        });
+      }
+
+      if self.included.insert(included_path.clone()) {
+        let included_source_string = self.included.len();
+        let included_content = fs::read_to_string(&included_path)?;
+        let included_content =
+          self.expand(&included_path, &included_content, included_source_string)?;
+
+        let _ = writeln!(output, "#line 1 {}", included_source_string);
+        output.push_str(&included_content);
+        let _ = writeln!(output, "#line {} {}", line_index + 2, source_string);
+      } else {
+        // keep the line numbers of the current file
+        output.push('\n');
+      }
+    }
+
+    self.stack.pop();
+    Ok(output)
+  }
+}
+
+/// Parse an `#include` directive.
+///
+/// Return `None` if the line is not an `#include` directive, and `Some(Err(()))` if it’s a malformed one.
+fn parse_include(line: &str) -> Option<Result<&str, ()>> {
+  let directive = line.trim_start().strip_prefix('#')?.trim_start();
+  let rest = directive.strip_prefix("include")?;
+
+  // something like #include_foo is another directive
+  if !rest.starts_with(|c: char| c.is_whitespace() || c == '"') {
+    return None;
+  }
+
+  let rest = match rest.trim_start().strip_prefix('"') {
+    Some(rest) => rest,
+    None => return Some(Err(())),
+  };
+
+  let (included, tail) = match rest.find('"') {
+    Some(end) => (&rest[..end], rest[end + 1..].trim()),
+    None => return Some(Err(())),
+  };
+
+  if included.is_empty() || !(tail.is_empty() || tail.starts_with("//")) {
+    return Some(Err(()));
+  }
+
+  Some(Ok(included))
+}
+
+/// Lexically remove `.` and `..` components, so that a file is always referred to by the same path.
+///
+/// A leading `.` is kept: it comes from the root directory (such as the default `.` one), and the watcher reports
+/// paths under it, so dependencies must keep it to be found again when they change.
+fn normalize(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+
+  for component in path.components() {
+    match component {
+      Component::CurDir if normalized.as_os_str().is_empty() => normalized.push(component),
+
+      Component::CurDir => (),
+
+      Component::ParentDir => {
+        let can_pop = matches!(
+          normalized.components().next_back(),
+          Some(Component::Normal(_))
+        );
+
+        if can_pop {
+          normalized.pop();
+        } else {
+          normalized.push(component);
+        }
+      }
+
+      _ => normalized.push(component),
+    }
+  }
+
+  normalized
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_utils::TempDir;
+
+  #[test]
+  fn include_directives() {
+    assert_eq!(parse_include("void main() {}"), None);
+    assert_eq!(parse_include("#version 330 core"), None);
+    assert_eq!(parse_include("#include_next <foo>"), None);
+    assert_eq!(
+      parse_include("#include \"noise.glsl\""),
+      Some(Ok("noise.glsl"))
+    );
+    assert_eq!(
+      parse_include("  #  include \"/lib/noise.glsl\" // fbm"),
+      Some(Ok("/lib/noise.glsl"))
+    );
+    assert_eq!(parse_include("#include <noise.glsl>"), Some(Err(())));
+    assert_eq!(parse_include("#include \"noise.glsl"), Some(Err(())));
+    assert_eq!(parse_include("#include \"\""), Some(Err(())));
+  }
+
+  #[test]
+  fn normalized_paths() {
+    assert_eq!(
+      normalize(Path::new("data/shaders/../lib/./noise.glsl")),
+      Path::new("data/lib/noise.glsl")
+    );
+    assert_eq!(normalize(Path::new("../lib")), Path::new("../lib"));
+    assert_eq!(
+      normalize(Path::new("./shaders/../lib/noise.glsl")),
+      Path::new("./lib/noise.glsl")
+    );
+  }
+
+  #[test]
+  fn resolve_includes() {
+    let root = TempDir::new("include");
+    let shaders = root.join("shaders");
+    let lib = root.join("lib");
+    fs::create_dir_all(&shaders).unwrap();
+    fs::create_dir_all(&lib).unwrap();
+
+    fs::write(lib.join("common.glsl"), "float pi = 3.14;\n").unwrap();
+    fs::write(
+      lib.join("noise.glsl"),
+      "#include \"common.glsl\"\nfloat noise() { return pi; }\n",
+    )
+    .unwrap();
+    fs::write(
+      shaders.join("a.vert"),
+      "#version 330 core\n#include \"/lib/noise.glsl\"\n#include \"../lib/common.glsl\"\nvoid main() {}\n",
+    )
+    .unwrap();
+
+    let resources = ResourceManager::new(root.to_path_buf());
+    let mut dmd = DecodingMetadata::from_source(shaders.join("a.shd.json"));
+    let src = read_stage(&resources, &shaders.join("a.vert"), &mut dmd).unwrap();
+
+    assert_eq!(
+      src,
+      "#version 330 core\n#line 1 1\n#line 1 2\nfloat pi = 3.14;\n#line 2 1\nfloat noise() { return pi; }\n#line 3 0\n\nvoid main() {}\n"
+    );
+    assert!(dmd.path_deps.contains(&lib.join("noise.glsl")));
+    assert!(dmd.path_deps.contains(&lib.join("common.glsl")));
+
+    // cycles are detected
+    fs::write(
+      lib.join("common.glsl"),
+      "#include \"noise.glsl\"\nfloat pi = 3.14;\n",
+    )
+    .unwrap();
+    let err = read_stage(&resources, &shaders.join("a.vert"), &mut dmd).unwrap_err();
+
+    match err {
+      ShaderError::IncludeCycle(cycle) => assert_eq!(
+        cycle,
+        vec![
+          shaders.join("a.vert"),
+          lib.join("noise.glsl"),
+          lib.join("common.glsl"),
+          lib.join("noise.glsl"),
+        ]
+      ),
+      err => panic!("unexpected error: {}", err),
+    }
+  }
+}
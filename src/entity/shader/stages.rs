@@ -0,0 +1,188 @@
+//! Single-file multi-stage shaders.
+//!
+//! A `.glsl` file can hold all the stages of a shader, each one starting with a `#pragma stage` directive:
+//!
+//! ```glsl
+//! #version 330 core
+//!
+//! #pragma stage vertex
+//! void main() {
+//!   gl_Position = vec4(0., 0., 0., 1.);
+//! }
+//!
+//! #pragma stage fragment
+//! out vec4 color;
+//!
+//! void main() {
+//!   color = vec4(1.);
+//! }
+//! ```
+//!
+//! The lines before the first directive are shared by all the stages. The stages are `vertex`, `tessellation-control`,
+//! `tessellation-evaluation`, `geometry` and `fragment`. In the source of a stage, the lines of the other stages are
+//! blanked out, so that line numbers reported by the GLSL compiler match the ones of the file.
+//!
+//! `.glsl` files without any `#pragma stage` directive are not shaders but, typically, files meant to be included.
+
+use crate::entity::shader::ShaderError;
+use std::path::Path;
+
+/// Sources of the stages of a single-file shader.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stages {
+  pub vert: Option<String>,
+  pub tess_ctrl: Option<String>,
+  pub tess_eval: Option<String>,
+  pub geo: Option<String>,
+  pub frag: Option<String>,
+}
+
+impl Stages {
+  fn get_mut(&mut self, stage: &str) -> Option<&mut Option<String>> {
+    match stage {
+      "vertex" => Some(&mut self.vert),
+      "tessellation-control" => Some(&mut self.tess_ctrl),
+      "tessellation-evaluation" => Some(&mut self.tess_eval),
+      "geometry" => Some(&mut self.geo),
+      "fragment" => Some(&mut self.frag),
+      _ => None,
+    }
+  }
+}
+
+/// Split the source of a single-file shader, read from `path`, into its stages.
+///
+/// Return `None` if the source doesn’t have any `#pragma stage` directive.
+pub fn split_stages(path: &Path, src: &str) -> Result<Option<Stages>, ShaderError> {
+  let lines: Vec<_> = src.lines().collect();
+  let directives: Vec<_> = lines
+    .iter()
+    .enumerate()
+    .filter_map(|(line_index, line)| parse_stage_directive(line).map(|stage| (line_index, stage)))
+    .collect();
+
+  let preamble_end = match directives.first() {
+    Some(&(line_index, _)) => line_index,
+    None => return Ok(None),
+  };
+
+  let mut stages = Stages::default();
+
+  for (i, &(line_index, stage)) in directives.iter().enumerate() {
+    let section_end = directives
+      .get(i + 1)
+      .map_or(lines.len(), |&(next_line_index, _)| next_line_index);
+
+    let slot = stages
+      .get_mut(stage)
+      .ok_or_else(|| ShaderError::UnknownStage {
+        path: path.to_owned(),
+        line: line_index + 1,
+        stage: stage.to_owned(),
+      })?;
+
+    if slot.is_some() {
+      return Err(ShaderError::DuplicateStage {
+        path: path.to_owned(),
+        line: line_index + 1,
+        stage: stage.to_owned(),
+      });
+    }
+
+    let mut stage_src = String::with_capacity(src.len());
+
+    for line in &lines[..preamble_end] {
+      stage_src.push_str(line);
+      stage_src.push('\n');
+    }
+
+    // blank out the other stages and the directive itself
+    for _ in preamble_end..=line_index {
+      stage_src.push('\n');
+    }
+
+    for line in &lines[line_index + 1..section_end] {
+      stage_src.push_str(line);
+      stage_src.push('\n');
+    }
+
+    *slot = Some(stage_src);
+  }
+
+  Ok(Some(stages))
+}
+
+/// Parse a `#pragma stage` directive and return the name of the stage.
+fn parse_stage_directive(line: &str) -> Option<&str> {
+  let directive = line.trim_start().strip_prefix('#')?.trim_start();
+  let rest = directive.strip_prefix("pragma")?;
+
+  if !rest.starts_with(char::is_whitespace) {
+    return None;
+  }
+
+  let rest = rest.trim_start().strip_prefix("stage")?;
+
+  if !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+    return None;
+  }
+
+  // ignore trailing comments
+  let stage = rest.split("//").next().unwrap_or("").trim();
+  Some(stage)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stage_directives() {
+    assert_eq!(
+      parse_stage_directive("#pragma stage vertex"),
+      Some("vertex")
+    );
+    assert_eq!(
+      parse_stage_directive("  # pragma  stage fragment // lighting"),
+      Some("fragment")
+    );
+    assert_eq!(parse_stage_directive("#pragma stage"), Some(""));
+    assert_eq!(parse_stage_directive("#pragma optimize(on)"), None);
+    assert_eq!(parse_stage_directive("#pragma stages vertex"), None);
+    assert_eq!(parse_stage_directive("void main() {}"), None);
+  }
+
+  #[test]
+  fn split() {
+    let path = Path::new("effect.glsl");
+    let src = "#version 330 core\n#pragma stage vertex\nvoid main() {}\n#pragma stage fragment\nout vec4 color;\n";
+    let stages = split_stages(path, src).unwrap().unwrap();
+
+    assert_eq!(
+      stages.vert.as_deref(),
+      Some("#version 330 core\n\nvoid main() {}\n")
+    );
+    assert_eq!(
+      stages.frag.as_deref(),
+      Some("#version 330 core\n\n\n\nout vec4 color;\n")
+    );
+    assert_eq!(stages.tess_ctrl, None);
+    assert_eq!(stages.tess_eval, None);
+    assert_eq!(stages.geo, None);
+
+    assert_eq!(split_stages(path, "float pi = 3.14;\n").unwrap(), None);
+
+    match split_stages(path, "#pragma stage vertex\n#pragma stage vertex\n") {
+      Err(ShaderError::DuplicateStage { line, .. }) => assert_eq!(line, 2),
+      _ => panic!("duplicate stage not detected"),
+    }
+
+    match split_stages(path, "#pragma stage pixel\n") {
+      Err(ShaderError::UnknownStage { line, stage, .. }) => {
+        assert_eq!(line, 1);
+        assert_eq!(stage, "pixel");
+      }
+      _ => panic!("unknown stage not detected"),
+    }
+  }
+}
@@ -0,0 +1,444 @@
+//! Offline shader validation.
+//!
+//! Shaders are checked on the GLSL ASTs of their stages, without any GPU, so that broken shaders are reported when
+//! they’re decoded instead of when they’re compiled:
+//!
+//! - Vertex inputs must be vertex attributes provided by meshes, named after
+//!   [`VertexSemantics`](crate::entity::mesh::VertexSemantics), with the same type.
+//! - Fragment inputs must be outputs of the stage right before the fragment stage, with the same type.
+//! - Uniforms declared in several stages must have the same type in all of them.
+//!
+//! Only global declarations are checked; interface blocks are ignored. Preprocessor conditionals are not evaluated, so
+//! declarations under `#if`, `#ifdef` and `#ifndef` are only used to match fragment inputs, whatever their type.
+
+use crate::entity::{
+  mesh::MeshVertex,
+  shader::{Shader, ShaderData},
+};
+use glsl::{
+  syntax::{
+    ArraySpecifier, Declaration, ExternalDeclaration, Preprocessor, ShaderStage, StorageQualifier,
+    TypeQualifier, TypeQualifierSpec, TypeSpecifier,
+  },
+  transpiler::glsl::{show_array_spec, show_type_specifier},
+};
+use luminance::vertex::{
+  Normalized, Vertex as _, VertexAttribDesc, VertexAttribDim, VertexAttribType,
+};
+use std::{collections::BTreeMap, error, fmt};
+
+/// Issue found while validating a shader.
+#[non_exhaustive]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidationError {
+  /// A vertex input is not a vertex attribute.
+  UnknownVertexInput { name: String },
+
+  /// A vertex input doesn’t have the type of the vertex attribute.
+  VertexInputTypeMismatch {
+    name: String,
+    attribute_ty: String,
+    input_ty: String,
+  },
+
+  /// A fragment input is not an output of the previous stage.
+  UnmatchedFragmentInput { name: String, stage: &'static str },
+
+  /// A fragment input doesn’t have the type of the matching output of the previous stage.
+  InterfaceTypeMismatch {
+    name: String,
+    stage: &'static str,
+    output_ty: String,
+    input_ty: String,
+  },
+
+  /// A uniform is declared with different types in two stages.
+  UniformTypeMismatch {
+    name: String,
+    stages: (&'static str, &'static str),
+    types: (String, String),
+  },
+}
+
+impl fmt::Display for ValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match *self {
+      ValidationError::UnknownVertexInput { ref name } => {
+        let attributes: Vec<_> = MeshVertex::vertex_desc()
+          .iter()
+          .map(|desc| desc.name)
+          .collect();
+        write!(
+          f,
+          "vertex input {} is not a vertex attribute (expected one of {})",
+          name,
+          attributes.join(", ")
+        )
+      }
+
+      ValidationError::VertexInputTypeMismatch {
+        ref name,
+        ref attribute_ty,
+        ref input_ty,
+      } => write!(
+        f,
+        "vertex input {} is a {} but the vertex attribute is a {}",
+        name, input_ty, attribute_ty
+      ),
+
+      ValidationError::UnmatchedFragmentInput {
+        ref name,
+        ref stage,
+      } => write!(
+        f,
+        "fragment input {} is not an output of the {} stage",
+        name, stage
+      ),
+
+      ValidationError::InterfaceTypeMismatch {
+        ref name,
+        ref stage,
+        ref output_ty,
+        ref input_ty,
+      } => write!(
+        f,
+        "fragment input {} is a {} but the {} stage outputs a {}",
+        name, input_ty, stage, output_ty
+      ),
+
+      ValidationError::UniformTypeMismatch {
+        ref name,
+        ref stages,
+        ref types,
+      } => write!(
+        f,
+        "uniform {} is a {} in the {} stage but a {} in the {} stage",
+        name, types.0, stages.0, types.1, stages.1
+      ),
+    }
+  }
+}
+
+impl error::Error for ValidationError {}
+
+/// Validate the interfaces between the stages of a shader, and its uniforms.
+///
+/// All the issues found are returned at once.
+pub fn validate(shader: &Shader) -> Result<(), Vec<ValidationError>> {
+  let vert = Globals::new(&shader.vert_shader.ast, false);
+  let frag = Globals::new(&shader.frag_shader.ast, true);
+  let optional_stage = |stage: &'static str, data: &Option<ShaderData>| {
+    data
+      .as_ref()
+      .map(|data| (stage, Globals::new(&data.ast, false)))
+  };
+
+  let stages: Vec<_> = Some(("vertex", vert))
+    .into_iter()
+    .chain(optional_stage(
+      "tessellation-control",
+      &shader.tess_ctrl_shader,
+    ))
+    .chain(optional_stage(
+      "tessellation-evaluation",
+      &shader.tess_eval_shader,
+    ))
+    .chain(optional_stage("geometry", &shader.geo_shader))
+    .chain(Some(("fragment", frag)))
+    .collect();
+
+  let mut errors = Vec::new();
+
+  // vertex inputs
+  let vertex_desc = MeshVertex::vertex_desc();
+  for input in stages[0].1.inputs.iter().filter(|input| !input.conditional) {
+    match vertex_desc.iter().find(|desc| desc.name == input.name) {
+      None => errors.push(ValidationError::UnknownVertexInput {
+        name: input.name.clone(),
+      }),
+
+      Some(desc) => {
+        let attribute_ty = attribute_type_name(&desc.attrib_desc);
+
+        if attribute_ty != input.ty {
+          errors.push(ValidationError::VertexInputTypeMismatch {
+            name: input.name.clone(),
+            attribute_ty,
+            input_ty: input.ty.clone(),
+          });
+        }
+      }
+    }
+  }
+
+  // fragment inputs; the stage right before the fragment one is the last one to output anything
+  let (previous_stage, previous) = &stages[stages.len() - 2];
+  let (_, frag) = &stages[stages.len() - 1];
+
+  for input in frag.inputs.iter().filter(|input| !input.conditional) {
+    let outputs: Vec<_> = previous
+      .outputs
+      .iter()
+      .filter(|output| output.name == input.name)
+      .collect();
+
+    // conditional outputs might have any type, depending on the active branch
+    match outputs
+      .iter()
+      .find(|output| !output.conditional)
+      .or_else(|| outputs.first())
+    {
+      None => errors.push(ValidationError::UnmatchedFragmentInput {
+        name: input.name.clone(),
+        stage: previous_stage,
+      }),
+
+      Some(output) if !output.conditional && output.ty != input.ty => {
+        errors.push(ValidationError::InterfaceTypeMismatch {
+          name: input.name.clone(),
+          stage: previous_stage,
+          output_ty: output.ty.clone(),
+          input_ty: input.ty.clone(),
+        })
+      }
+
+      Some(_) => (),
+    }
+  }
+
+  // uniforms shared by several stages
+  let mut uniforms: BTreeMap<&str, (&'static str, &str)> = BTreeMap::new();
+
+  for (stage, globals) in &stages {
+    for uniform in globals
+      .uniforms
+      .iter()
+      .filter(|uniform| !uniform.conditional)
+    {
+      match uniforms.get(uniform.name.as_str()) {
+        Some(&(known_stage, known_ty)) if known_ty != uniform.ty => {
+          errors.push(ValidationError::UniformTypeMismatch {
+            name: uniform.name.clone(),
+            stages: (known_stage, stage),
+            types: (known_ty.to_owned(), uniform.ty.clone()),
+          })
+        }
+
+        Some(_) => (),
+
+        None => {
+          uniforms.insert(&uniform.name, (stage, &uniform.ty));
+        }
+      }
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(errors)
+  }
+}
+
+/// Global variable declared with a storage qualifier.
+#[derive(Debug)]
+struct Global {
+  name: String,
+  /// GLSL type, including array dimensions.
+  ty: String,
+  /// Whether the declaration is under a preprocessor conditional.
+  conditional: bool,
+}
+
+/// Inputs, outputs and uniforms of a stage.
+#[derive(Debug, Default)]
+struct Globals {
+  inputs: Vec<Global>,
+  outputs: Vec<Global>,
+  uniforms: Vec<Global>,
+}
+
+impl Globals {
+  /// Collect the globals of a stage; `varying` variables are inputs of the fragment stage and outputs of the others.
+  fn new(ast: &ShaderStage, fragment: bool) -> Self {
+    let mut globals = Globals::default();
+    // nesting depth of preprocessor conditionals
+    let mut depth = 0usize;
+
+    for declaration in &ast.0 {
+      let list = match declaration {
+        ExternalDeclaration::Preprocessor(Preprocessor::If(_))
+        | ExternalDeclaration::Preprocessor(Preprocessor::IfDef(_))
+        | ExternalDeclaration::Preprocessor(Preprocessor::IfNDef(_)) => {
+          depth += 1;
+          continue;
+        }
+
+        ExternalDeclaration::Preprocessor(Preprocessor::EndIf) => {
+          depth = depth.saturating_sub(1);
+          continue;
+        }
+
+        ExternalDeclaration::Declaration(Declaration::InitDeclaratorList(list)) => list,
+        _ => continue,
+      };
+
+      let storage = list.head.ty.qualifier.as_ref().and_then(storage_qualifier);
+      let target = match storage {
+        Some(StorageQualifier::In) | Some(StorageQualifier::Attribute) => &mut globals.inputs,
+        Some(StorageQualifier::Out) => &mut globals.outputs,
+        Some(StorageQualifier::Varying) if fragment => &mut globals.inputs,
+        Some(StorageQualifier::Varying) => &mut globals.outputs,
+        Some(StorageQualifier::Uniform) => &mut globals.uniforms,
+        _ => continue,
+      };
+
+      let ty = &list.head.ty.ty;
+      let head = list
+        .head
+        .name
+        .as_ref()
+        .map(|name| (name, list.head.array_specifier.as_ref()));
+      let tail = list
+        .tail
+        .iter()
+        .map(|decl| (&decl.ident.ident, decl.ident.array_spec.as_ref()));
+
+      for (name, array) in head.into_iter().chain(tail) {
+        target.push(Global {
+          name: name.0.clone(),
+          ty: type_name(ty, array),
+          conditional: depth > 0,
+        });
+      }
+    }
+
+    globals
+  }
+}
+
+/// Storage qualifier of a declaration, if any.
+fn storage_qualifier(qualifier: &TypeQualifier) -> Option<StorageQualifier> {
+  qualifier.qualifiers.0.iter().find_map(|spec| match spec {
+    TypeQualifierSpec::Storage(storage) => Some(storage.clone()),
+    _ => None,
+  })
+}
+
+/// GLSL name of a type, with the array dimensions of the declarator if any.
+fn type_name(ty: &TypeSpecifier, array: Option<&ArraySpecifier>) -> String {
+  let mut name = String::new();
+  show_type_specifier(&mut name, ty);
+
+  if let Some(array) = array {
+    show_array_spec(&mut name, array);
+  }
+
+  name
+}
+
+/// GLSL type of a vertex attribute; normalized integers are read as floats.
+fn attribute_type_name(desc: &VertexAttribDesc) -> String {
+  let (prefix, scalar) = match desc.ty {
+    VertexAttribType::Integral(Normalized::No) => ("i", "int"),
+    VertexAttribType::Unsigned(Normalized::No) | VertexAttribType::Boolean => ("u", "uint"),
+    _ => ("", "float"),
+  };
+
+  match desc.dim {
+    VertexAttribDim::Dim1 => scalar.to_owned(),
+    VertexAttribDim::Dim2 => format!("{}vec2", prefix),
+    VertexAttribDim::Dim3 => format!("{}vec3", prefix),
+    VertexAttribDim::Dim4 => format!("{}vec4", prefix),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use glsl::parser::Parse as _;
+
+  fn shader(vert: &str, geo: Option<&str>, frag: &str) -> Shader {
+    let stage = |src: &str| ShaderData::new(src, ShaderStage::parse(src).unwrap());
+
+    Shader {
+      name: "test".to_owned(),
+      vert_shader: stage(vert),
+      tess_ctrl_shader: None,
+      tess_eval_shader: None,
+      geo_shader: geo.map(stage),
+      frag_shader: stage(frag),
+    }
+  }
+
+  #[test]
+  fn valid_shader() {
+    let vert = "in vec3 pos;\nin vec3 nor;\nout vec3 v_nor;\nuniform mat4 view;\nvoid main() {}\n";
+    let frag = "in vec3 v_nor;\nout vec4 color;\nuniform mat4 view;\nvoid main() {}\n";
+
+    assert_eq!(validate(&shader(vert, None, frag)), Ok(()));
+  }
+
+  #[test]
+  fn invalid_shader() {
+    let vert = "in vec3 pos;\nin vec3 normal;\nin vec3 uv;\nout vec3 v_nor;\nuniform mat4 view;\nvoid main() {}\n";
+    let frag =
+      "in vec4 v_nor;\nin vec2 v_uv;\nout vec4 color;\nuniform mat3 view;\nvoid main() {}\n";
+
+    assert_eq!(
+      validate(&shader(vert, None, frag)),
+      Err(vec![
+        ValidationError::UnknownVertexInput {
+          name: "normal".to_owned()
+        },
+        ValidationError::VertexInputTypeMismatch {
+          name: "uv".to_owned(),
+          attribute_ty: "vec2".to_owned(),
+          input_ty: "vec3".to_owned(),
+        },
+        ValidationError::InterfaceTypeMismatch {
+          name: "v_nor".to_owned(),
+          stage: "vertex",
+          output_ty: "vec3".to_owned(),
+          input_ty: "vec4".to_owned(),
+        },
+        ValidationError::UnmatchedFragmentInput {
+          name: "v_uv".to_owned(),
+          stage: "vertex",
+        },
+        ValidationError::UniformTypeMismatch {
+          name: "view".to_owned(),
+          stages: ("vertex", "fragment"),
+          types: ("mat4".to_owned(), "mat3".to_owned()),
+        },
+      ])
+    );
+  }
+
+  #[test]
+  fn fragment_inputs_come_from_the_previous_stage() {
+    let vert = "in vec3 pos;\nout vec3 v_pos;\nvoid main() {}\n";
+    let geo = "in vec3 v_pos[];\nout vec3 g_pos;\nvoid main() {}\n";
+    let frag = "in vec3 g_pos;\nout vec4 color;\nvoid main() {}\n";
+
+    assert_eq!(validate(&shader(vert, Some(geo), frag)), Ok(()));
+
+    let frag = "in vec3 v_pos;\nout vec4 color;\nvoid main() {}\n";
+
+    assert_eq!(
+      validate(&shader(vert, Some(geo), frag)),
+      Err(vec![ValidationError::UnmatchedFragmentInput {
+        name: "v_pos".to_owned(),
+        stage: "geometry",
+      }])
+    );
+  }
+
+  #[test]
+  fn conditional_declarations() {
+    let vert = "in vec3 pos;\n#ifdef FOG\nout float v_depth;\nuniform vec3 fog_color;\n#else\nuniform vec4 fog_color;\n#endif\nvoid main() {}\n";
+    let frag = "#ifdef INSTANCED\nin vec3 offset;\n#endif\nin vec2 v_depth;\nout vec4 color;\nuniform mat4 fog_color;\nvoid main() {}\n";
+
+    assert_eq!(validate(&shader(vert, None, frag)), Ok(()));
+  }
+}
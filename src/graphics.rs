@@ -17,6 +17,7 @@ use crate::{
 };
 use cgmath::{Deg, Rad, Vector3};
 use glfw::{Action, Context as _, Key, MouseButton, WindowEvent};
+use luminance::vertex::{Vertex as _, VertexDesc};
 use luminance_front::{
   context::GraphicsContext as _,
   shader::{BuiltProgram, Program},
@@ -24,7 +25,7 @@ use luminance_front::{
 };
 use luminance_glfw::{GlfwSurface, GlfwSurfaceError};
 use luminance_windowing::WindowOpt;
-use shader::{DynamicUniformInterface, ShaderASTs};
+use shader::{tessellation_stages, DynamicUniformInterface, ShaderASTs};
 use std::{collections::HashMap, fmt, sync::Arc};
 
 const TITLE: &str = "Spectra";
@@ -125,12 +126,43 @@ impl GraphicsSystem {
     }
   }
 
+  /// Drop the GPU objects associated with an entity.
+  fn drop_entity(&mut self, handle: Handle<Entity>) {
+    if self.meshes.remove(&handle).is_some() {
+      log::info!("mesh {} removed from the GPU", handle);
+    }
+
+    if self.shaders.remove(&handle).is_some() {
+      log::info!("shader {} removed from the GPU", handle);
+    }
+  }
+
   /// Accept a mesh.
   fn accept_mesh(&mut self, handle: Handle<Entity>, mesh: Arc<Mesh>) {
     log::info!("accepting mesh {}", handle);
     log::debug!("building GPU tessellation for mesh {}", handle);
 
     let mesh = &*mesh;
+    let vertex_format = mesh.vertex_format();
+    let defaulted = match defaulted_attributes(&vertex_format) {
+      Ok(defaulted) => defaulted,
+      Err(name) => {
+        log::error!(
+          "cannot accept mesh handle {} because its vertex attribute {} is not part of the GPU vertex layout",
+          handle,
+          name
+        );
+        return;
+      }
+    };
+
+    log::debug!(
+      "mesh {} vertex format: {}; defaulted: {}",
+      handle,
+      attribute_names(&vertex_format),
+      defaulted.join(", ")
+    );
+
     let tess_res = self
       .surface
       .new_tess()
@@ -168,13 +200,15 @@ impl GraphicsSystem {
     log::debug!("building GPU shader {}", handle);
 
     let shader = &*shader;
-    let mut asts = ShaderASTs {
-      vert_ast: &shader.vert_shader.ast,
-      tess_ctrl_ast: None,
-      tess_eval_ast: None,
-      geo_ast: None,
-      frag_ast: &shader.frag_shader.ast,
+    let tess_stages = match tessellation_stages(shader) {
+      Ok(tess_stages) => tess_stages,
+      Err(err) => {
+        log::error!("cannot compile shader {}: {}", handle, err);
+        return;
+      }
     };
+    let geo_stage = shader.geo_shader.as_ref().map(|geo| geo.raw.as_str());
+    let mut asts = ShaderASTs::new(shader);
 
     // compile the shader
     let compilation = self
@@ -182,8 +216,8 @@ impl GraphicsSystem {
       .new_shader_program::<VertexSemantics, (), DynamicUniformInterface>()
       .from_strings_env(
         &shader.vert_shader.raw,
-        None,
-        None,
+        tess_stages,
+        geo_stage,
         &shader.frag_shader.raw,
         &mut asts,
       );
@@ -204,6 +238,35 @@ impl GraphicsSystem {
   }
 }
 
+/// Check that a mesh vertex format fits the GPU vertex layout, [`MeshVertex`].
+///
+/// Return the names of the attributes of the layout the mesh doesn’t provide, which the tessellation fills with
+/// default values, or the name of the first attribute of the format that isn’t part of the layout.
+fn defaulted_attributes(vertex_format: &VertexDesc) -> Result<Vec<&'static str>, &'static str> {
+  let layout = MeshVertex::vertex_desc();
+
+  if let Some(desc) = vertex_format.iter().find(|desc| !layout.contains(desc)) {
+    return Err(desc.name);
+  }
+
+  Ok(
+    layout
+      .into_iter()
+      .filter(|desc| !vertex_format.contains(desc))
+      .map(|desc| desc.name)
+      .collect(),
+  )
+}
+
+/// Comma-separated names of the attributes of a vertex format.
+fn attribute_names(vertex_format: &VertexDesc) -> String {
+  vertex_format
+    .iter()
+    .map(|desc| desc.name)
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
 impl System for GraphicsSystem {
   type Addr = Addr<GraphicsMsg>;
 
@@ -234,6 +297,13 @@ impl System for GraphicsSystem {
           GraphicsMsg::EntityEvent(EntityEvent::Loaded { handle, entity }) => {
             self.accept_entity(handle, entity)
           }
+
+          GraphicsMsg::EntityEvent(EntityEvent::Removed { handle }) => self.drop_entity(handle),
+
+          // keep showing the last good version of whatever failed to decode
+          GraphicsMsg::EntityEvent(EntityEvent::Failed { .. }) => (),
+
+          GraphicsMsg::EntityEvent(EntityEvent::Traversed { .. }) => (),
         }
       }
 
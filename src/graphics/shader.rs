@@ -1,5 +1,6 @@
 //! Shaders on the GPU.
 
+use crate::entity::shader::Shader;
 use glsl::{
   syntax::{
     FullySpecifiedType, ShaderStage, SingleDeclaration, StorageQualifier, TypeQualifier,
@@ -7,12 +8,12 @@ use glsl::{
   },
   visitor::{Host as _, Visit, Visitor},
 };
-use luminance::shader::{UniformBuilder, UniformWarning};
+use luminance::shader::{TessellationStages, UniformBuilder, UniformWarning};
 use luminance_front::{
   shader::{Uniform, UniformInterface},
   Backend,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, error, fmt};
 
 /// Dynamic uniform interface for shaders.
 ///
@@ -66,6 +67,62 @@ pub struct ShaderASTs<'a> {
   pub frag_ast: &'a ShaderStage,
 }
 
+impl<'a> ShaderASTs<'a> {
+  /// Gather the ASTs of all the stages of a shader.
+  pub fn new(shader: &'a Shader) -> Self {
+    Self {
+      vert_ast: &shader.vert_shader.ast,
+      tess_ctrl_ast: shader.tess_ctrl_shader.as_ref().map(|data| &data.ast),
+      tess_eval_ast: shader.tess_eval_shader.as_ref().map(|data| &data.ast),
+      geo_ast: shader.geo_shader.as_ref().map(|data| &data.ast),
+      frag_ast: &shader.frag_shader.ast,
+    }
+  }
+}
+
+/// Sources of the tessellation stages of a shader.
+///
+/// Tessellation requires both a control and an evaluation stage; a shader with only one of them is rejected.
+pub fn tessellation_stages(
+  shader: &Shader,
+) -> Result<Option<TessellationStages<str>>, InvalidStages> {
+  match (&shader.tess_ctrl_shader, &shader.tess_eval_shader) {
+    (Some(control), Some(evaluation)) => Ok(Some(TessellationStages {
+      control: control.raw.as_str(),
+      evaluation: evaluation.raw.as_str(),
+    })),
+    (Some(_), None) => Err(InvalidStages::MissingTessEvaluation),
+    (None, Some(_)) => Err(InvalidStages::MissingTessControl),
+    (None, None) => Ok(None),
+  }
+}
+
+/// Invalid combination of shader stages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum InvalidStages {
+  /// A tessellation control stage was provided without a tessellation evaluation stage.
+  MissingTessEvaluation,
+
+  /// A tessellation evaluation stage was provided without a tessellation control stage.
+  MissingTessControl,
+}
+
+impl fmt::Display for InvalidStages {
+  fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    match *self {
+      InvalidStages::MissingTessEvaluation => f.write_str(
+        "tessellation control stage without tessellation evaluation stage; both are required",
+      ),
+      InvalidStages::MissingTessControl => f.write_str(
+        "tessellation evaluation stage without tessellation control stage; both are required",
+      ),
+    }
+  }
+}
+
+impl error::Error for InvalidStages {}
+
 impl<'b> UniformInterface<Backend, ShaderASTs<'b>> for DynamicUniformInterface {
   fn uniform_interface<'a>(
     builder: &mut UniformBuilder<'a, Backend>,
@@ -97,6 +154,7 @@ impl<'b> UniformInterface<Backend, ShaderASTs<'b>> for DynamicUniformInterface {
 /// This type allows to get information about uniforms (name and types) by traversing GLSL ASTs.
 struct ExtractUniforms {
   uniforms: HashMap<String, TypeSpecifierNonArray>,
+  /// Uniforms declared again with another type, along with that type.
   errors: Vec<(String, TypeSpecifierNonArray)>,
 }
 
@@ -148,8 +206,8 @@ impl ExtractUniforms {
       .collect();
 
     for (name, ty) in self.errors {
-      log::warn!(
-        "dynamic uniform interface error for uniform {}, which type is {:?}",
+      log::error!(
+        "uniform {} is declared with conflicting types; ignoring its {:?} declaration",
         name,
         ty
       )
@@ -180,10 +238,13 @@ impl Visitor for ExtractUniforms {
           let name = name.0.clone();
           let ty = ty.clone();
 
-          if self.uniforms.contains_key(&name) {
-            self.errors.push((name, ty));
-          } else {
-            self.uniforms.insert(name, ty);
+          // the same uniform can be declared in several stages, as long as it has the same type
+          match self.uniforms.get(&name) {
+            Some(known_ty) if *known_ty != ty => self.errors.push((name, ty)),
+            Some(_) => (),
+            None => {
+              self.uniforms.insert(name, ty);
+            }
           }
         }
       }
@@ -198,6 +259,7 @@ impl Visitor for ExtractUniforms {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::entity::shader::ShaderData;
   use glsl::parser::Parse as _;
 
   #[test]
@@ -227,4 +289,60 @@ mod tests {
       Some(&TypeSpecifierNonArray::Int)
     );
   }
+
+  #[test]
+  fn shared_uniforms() {
+    let vs = "uniform float t;\nuniform vec2 offset;\nvoid main() {}";
+    let fs = "uniform float t;\nuniform vec3 offset;\nvoid main() {}";
+    let mut vs_ast = glsl::syntax::ShaderStage::parse(vs).unwrap();
+    let mut fs_ast = glsl::syntax::ShaderStage::parse(fs).unwrap();
+    let mut extractor = ExtractUniforms::new();
+
+    vs_ast.visit(&mut extractor);
+    fs_ast.visit(&mut extractor);
+
+    assert_eq!(
+      extractor.uniforms.get("t"),
+      Some(&TypeSpecifierNonArray::Float)
+    );
+    assert_eq!(
+      extractor.uniforms.get("offset"),
+      Some(&TypeSpecifierNonArray::Vec2)
+    );
+    assert_eq!(
+      extractor.errors,
+      vec![("offset".to_owned(), TypeSpecifierNonArray::Vec3)]
+    );
+  }
+
+  #[test]
+  fn tessellation_stage_combinations() {
+    let stage = || {
+      let src = "void main() {}";
+      ShaderData::new(src, glsl::syntax::ShaderStage::parse(src).unwrap())
+    };
+    let shader =
+      |tess_ctrl_shader: Option<ShaderData>, tess_eval_shader: Option<ShaderData>| Shader {
+        name: "test".to_owned(),
+        vert_shader: stage(),
+        tess_ctrl_shader,
+        tess_eval_shader,
+        geo_shader: None,
+        frag_shader: stage(),
+      };
+
+    assert!(matches!(tessellation_stages(&shader(None, None)), Ok(None)));
+    assert!(matches!(
+      tessellation_stages(&shader(Some(stage()), Some(stage()))),
+      Ok(Some(_))
+    ));
+    assert_eq!(
+      tessellation_stages(&shader(Some(stage()), None)).err(),
+      Some(InvalidStages::MissingTessEvaluation)
+    );
+    assert_eq!(
+      tessellation_stages(&shader(None, Some(stage()))).err(),
+      Some(InvalidStages::MissingTessControl)
+    );
+  }
 }
@@ -3,3 +3,6 @@ pub mod graphics;
 pub mod proto;
 pub mod runtime;
 pub mod system;
+
+#[cfg(test)]
+mod test_utils;
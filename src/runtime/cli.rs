@@ -6,4 +6,13 @@ use structopt::StructOpt;
 pub struct CLI {
   #[structopt(short = "r", long, default_value = ".")]
   pub entity_root_path: PathBuf,
+
+  /// Directory where decoded meshes are cached; caching is disabled if absent.
+  #[structopt(long)]
+  pub mesh_cache_path: Option<PathBuf>,
+
+  /// Decode all the resources once and exit, with a non-zero status if any of them cannot be decoded or if any shader
+  /// fails validation.
+  #[structopt(long)]
+  pub check: bool,
 }
@@ -11,7 +11,7 @@ use spectra::{
   runtime::RuntimeMsg,
   system::{system_init, Addr, MsgQueue, Publisher as _, System, SystemUID},
 };
-use std::{collections::HashSet, sync::mpsc::sync_channel, thread};
+use std::{collections::HashSet, process, sync::mpsc::sync_channel, thread};
 use structopt::StructOpt;
 
 /// Runtime system.
@@ -68,6 +68,31 @@ impl System for Runtime {
     let entity_uid = self.create_system("entity");
     let mut entity_system: EntitySystem =
       EntitySystem::new(self.system_addr(), entity_uid, cli.entity_root_path);
+
+    if cli.check {
+      let failures = entity_system.check();
+
+      for (path, err) in &failures {
+        log::error!(
+          "cannot decode {}: {}",
+          path.display().to_string().purple().italic(),
+          err
+        );
+      }
+
+      if failures.is_empty() {
+        log::info!("all resources decoded successfully");
+        process::exit(0);
+      } else {
+        log::error!("{} resources cannot be decoded", failures.len());
+        process::exit(1);
+      }
+    }
+
+    if let Some(mesh_cache_path) = cli.mesh_cache_path {
+      entity_system.enable_mesh_cache(mesh_cache_path);
+    }
+
     let entity_system_addr = entity_system.system_addr();
 
     // graphics system
@@ -35,6 +35,8 @@ where
   fn subscribe(&mut self, subscriber: impl Recipient<M> + 'static);
 
   /// Publish events to all subscribers.
+  ///
+  /// Subscribers that cannot receive events anymore, such as stopped systems, are dropped.
   fn publish(&self, event: M);
 }
 
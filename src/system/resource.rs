@@ -8,7 +8,12 @@
 use crate::entity::decoder::DecodingMetadata;
 use colored::Colorize as _;
 use std::{
-  cmp::Ordering, collections::HashMap, fmt, marker::PhantomData, path::Path, path::PathBuf,
+  cmp::Ordering,
+  collections::{BTreeSet, HashMap, HashSet},
+  fmt,
+  marker::PhantomData,
+  path::Path,
+  path::PathBuf,
 };
 
 /// Simple handle systems can talk about.
@@ -20,6 +25,8 @@ pub struct Handle<T> {
 
 unsafe impl<T> Send for Handle<T> {}
 
+unsafe impl<T> Sync for Handle<T> {}
+
 impl<T> Copy for Handle<T> {}
 
 impl<T> Clone for Handle<T> {
@@ -72,9 +79,21 @@ pub struct ResourceManager<T> {
 
   /// Reversed dependency paths <=> resources.
   ///
-  /// Associate a [`Handle`] — corresponding to the resource responsible for handling that path — to a given path.
-  /// This will be used to reload / tell the resource this path has changed.
-  path_deps_mappings: HashMap<PathBuf, Handle<T>>,
+  /// Associate the [`Handle`]s — corresponding to the resources responsible for handling that path — to a given path.
+  /// This will be used to reload / tell the resources this path has changed. Several resources can depend on the same
+  /// path, such as shaders including the same file.
+  path_deps_mappings: HashMap<PathBuf, BTreeSet<Handle<T>>>,
+
+  /// Paths resources were decoded from.
+  ///
+  /// When a path dependency changes, its owner is reloaded by decoding its source path again.
+  sources: HashMap<Handle<T>, PathBuf>,
+
+  /// Dependency paths of files that failed to decode, indexed by the path of those files.
+  ///
+  /// A file failing to decode has no resource to own its dependencies; they are kept here so that fixing any of them
+  /// decodes the file again.
+  failed_path_deps: HashMap<PathBuf, HashSet<PathBuf>>,
 }
 
 impl<T> ResourceManager<T> {
@@ -89,6 +108,8 @@ impl<T> ResourceManager<T> {
       resources: HashMap::new(),
       translations: HashMap::new(),
       path_deps_mappings: HashMap::new(),
+      sources: HashMap::new(),
+      failed_path_deps: HashMap::new(),
     }
   }
 
@@ -174,26 +195,36 @@ impl<T> ResourceManager<T> {
   }
 
   fn register_decoding_metadata(&mut self, handle: Handle<T>, decoding_metadata: DecodingMetadata) {
-    for path in decoding_metadata.path_deps {
-      let ancient_handle = self.path_deps_mappings.insert(path.clone(), handle);
+    if let Some(source) = decoding_metadata.source {
+      let _ = self.sources.insert(handle, source);
+    }
 
-      if let Some(ancient_handle) = ancient_handle {
-        log::debug!(
-          "changed owner from {} to {} for path dependency {}",
-          ancient_handle,
-          handle,
-          path.display().to_string().purple().italic()
-        );
-      } else {
-        log::debug!(
-          "registered owner {} for path dependency {}",
-          handle,
-          path.display().to_string().purple().italic()
-        );
-      }
+    // forget about the previous dependencies; the resource might not depend on them anymore
+    self.forget_path_deps(handle);
+
+    for path in decoding_metadata.path_deps {
+      log::debug!(
+        "registered owner {} for path dependency {}",
+        handle,
+        path.display().to_string().purple().italic()
+      );
+
+      let _ = self
+        .path_deps_mappings
+        .entry(path)
+        .or_default()
+        .insert(handle);
     }
   }
 
+  /// Remove a resource from the owners of all the path dependencies.
+  fn forget_path_deps(&mut self, handle: Handle<T>) {
+    self.path_deps_mappings.retain(|_, owners| {
+      let _ = owners.remove(&handle);
+      !owners.is_empty()
+    });
+  }
+
   /// Translate a resource name into a handle.
   ///
   /// This function allows to check whether a resource is already registered and eventually modify it.
@@ -229,6 +260,63 @@ impl<T> ResourceManager<T> {
     }
   }
 
+  /// Find all the resources owning a path dependency. Handles are sorted.
+  pub fn dependency_owners(&self, path: impl AsRef<Path>) -> Vec<Handle<T>> {
+    self
+      .path_deps_mappings
+      .get(path.as_ref())
+      .map(|owners| owners.iter().copied().collect())
+      .unwrap_or_default()
+  }
+
+  /// Record the dependencies of a file that failed to decode, replacing the ones of a previous failure.
+  pub fn register_failed_path_deps(
+    &mut self,
+    source: impl Into<PathBuf>,
+    path_deps: HashSet<PathBuf>,
+  ) {
+    let source = source.into();
+
+    for path in &path_deps {
+      log::debug!(
+        "registered failed {} for path dependency {}",
+        source.display().to_string().purple().italic(),
+        path.display().to_string().purple().italic()
+      );
+    }
+
+    let _ = self.failed_path_deps.insert(source, path_deps);
+  }
+
+  /// Forget about the dependencies of files that failed to decode, for all files under `source`.
+  ///
+  /// This must be called once those files decode successfully or disappear.
+  pub fn forget_failed_path_deps(&mut self, source: impl AsRef<Path>) {
+    let source = source.as_ref();
+    self
+      .failed_path_deps
+      .retain(|failed_source, _| !failed_source.starts_with(source));
+  }
+
+  /// Find all the files that failed to decode while depending on a path. Paths are sorted.
+  pub fn failed_dependents(&self, path: impl AsRef<Path>) -> Vec<PathBuf> {
+    let path = path.as_ref();
+    let mut sources: Vec<_> = self
+      .failed_path_deps
+      .iter()
+      .filter(|(_, path_deps)| path_deps.contains(path))
+      .map(|(source, _)| source.clone())
+      .collect();
+
+    sources.sort();
+    sources
+  }
+
+  /// Path the resource referred to by the input handle was decoded from, if any.
+  pub fn source_path(&self, handle: Handle<T>) -> Option<&Path> {
+    self.sources.get(&handle).map(PathBuf::as_path)
+  }
+
   /// Lookup the resource referred to by the input handle.
   pub fn get(&self, handle: Handle<T>) -> Option<&T> {
     self.resources.get(&handle)
@@ -239,8 +327,39 @@ impl<T> ResourceManager<T> {
     self.resources.get_mut(&handle)
   }
 
+  /// Iterate over all the resources currently held by the manager.
+  ///
+  /// Handles that are only reserved are not part of the iteration.
+  pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+    self
+      .resources
+      .iter()
+      .map(|(handle, resource)| (*handle, resource))
+  }
+
+  /// Find all the resources decoded from `path`.
+  ///
+  /// If `path` is a directory, resources decoded from any file inside it are returned. Handles are sorted.
+  pub fn sourced_from(&self, path: impl AsRef<Path>) -> Vec<Handle<T>> {
+    let path = path.as_ref();
+    let mut handles: Vec<_> = self
+      .sources
+      .iter()
+      .filter(|(_, source)| source.starts_with(path))
+      .map(|(handle, _)| *handle)
+      .collect();
+
+    handles.sort();
+    handles
+  }
+
   /// Get rid of a resource.
+  ///
+  /// The name of the resource stays bound to its [`Handle`], so that if it gets loaded again later, the same handle
+  /// is used.
   pub fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+    let _ = self.sources.remove(&handle);
+    self.forget_path_deps(handle);
     self.resources.remove(&handle)
   }
 
@@ -274,4 +393,103 @@ mod tests {
       Path::new("data/foo/test.json")
     );
   }
+
+  #[test]
+  fn dependency_owners() {
+    let mut res_mgr = ResourceManager::<String>::new(Path::new("data"));
+    let mut dmd = DecodingMetadata::from_source("data/foo.shd.json");
+    dmd.add_dep("data/foo.vert");
+    dmd.add_dep("data/foo.frag");
+
+    let handle = res_mgr.wrap("foo".to_owned(), "foo", dmd);
+
+    assert_eq!(res_mgr.dependency_owners("data/foo.vert"), vec![handle]);
+    assert_eq!(res_mgr.dependency_owners("data/foo.frag"), vec![handle]);
+    assert!(res_mgr.dependency_owners("data/bar.frag").is_empty());
+    assert_eq!(
+      res_mgr.source_path(handle),
+      Some(Path::new("data/foo.shd.json"))
+    );
+
+    // reloading with different dependencies drops the old ones
+    let handle = res_mgr.wrap(
+      "foo".to_owned(),
+      "foo",
+      DecodingMetadata::with_deps(vec![PathBuf::from("data/bar.frag")]),
+    );
+
+    assert!(res_mgr.dependency_owners("data/foo.vert").is_empty());
+    assert_eq!(res_mgr.dependency_owners("data/bar.frag"), vec![handle]);
+
+    // several resources can share a dependency
+    let other = res_mgr.wrap(
+      "other".to_owned(),
+      "other",
+      DecodingMetadata::with_deps(vec![PathBuf::from("data/bar.frag")]),
+    );
+
+    assert_eq!(
+      res_mgr.dependency_owners("data/bar.frag"),
+      vec![handle, other]
+    );
+
+    let _ = res_mgr.remove(handle);
+    assert_eq!(res_mgr.dependency_owners("data/bar.frag"), vec![other]);
+  }
+
+  #[test]
+  fn failed_dependents() {
+    let mut res_mgr = ResourceManager::<String>::new(Path::new("data"));
+    let path_deps: HashSet<_> = vec![PathBuf::from("data/a.frag"), PathBuf::from("data/lib.glsl")]
+      .into_iter()
+      .collect();
+
+    res_mgr.register_failed_path_deps("data/a.shd.json", path_deps.clone());
+    res_mgr.register_failed_path_deps("data/b.shd.json", path_deps);
+
+    assert_eq!(
+      res_mgr.failed_dependents("data/lib.glsl"),
+      vec![
+        PathBuf::from("data/a.shd.json"),
+        PathBuf::from("data/b.shd.json")
+      ]
+    );
+    assert!(res_mgr.failed_dependents("data/b.frag").is_empty());
+
+    res_mgr.forget_failed_path_deps("data/a.shd.json");
+    assert_eq!(
+      res_mgr.failed_dependents("data/a.frag"),
+      vec![PathBuf::from("data/b.shd.json")]
+    );
+  }
+
+  #[test]
+  fn remove_by_source() {
+    let mut res_mgr = ResourceManager::<String>::new(Path::new("data"));
+    let a = res_mgr.wrap(
+      "a".to_owned(),
+      "a",
+      DecodingMetadata::from_source("data/params.json"),
+    );
+    let b = res_mgr.wrap(
+      "b".to_owned(),
+      "b",
+      DecodingMetadata::from_source("data/params.json"),
+    );
+    let c = res_mgr.wrap(
+      "c".to_owned(),
+      "c",
+      DecodingMetadata::from_source("data/meshes/c.obj"),
+    );
+
+    assert_eq!(res_mgr.sourced_from("data/params.json"), vec![a, b]);
+    assert_eq!(res_mgr.sourced_from("data/meshes"), vec![c]);
+    assert_eq!(res_mgr.sourced_from("data"), vec![a, b, c]);
+
+    assert_eq!(res_mgr.remove(c), Some("c".to_owned()));
+    assert!(res_mgr.sourced_from("data/meshes").is_empty());
+
+    // the name is still bound to the same handle
+    assert_eq!(res_mgr.ask("c"), Some(c));
+  }
 }
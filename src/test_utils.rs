@@ -0,0 +1,56 @@
+//! Helpers shared by tests.
+
+use std::{
+  env, fs,
+  ops::Deref,
+  path::{Path, PathBuf},
+  process,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Temporary directory, removed along with its contents when dropped — even if the test fails.
+///
+/// Directories are unique, even among tests running concurrently in the same process.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  /// Create a new, empty temporary directory; `name` helps finding out which test it belongs to.
+  pub fn new(name: &str) -> Self {
+    Self::new_in(&env::temp_dir(), name)
+  }
+
+  /// Create a new, empty temporary directory in `dir`, which can be relative to the current directory.
+  pub fn new_in(dir: &Path, name: &str) -> Self {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("spectra-{}-{}-{}", name, process::id(), id));
+
+    // leftover of a previous process with the same ID
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+
+    Self(path)
+  }
+}
+
+impl Deref for TempDir {
+  type Target = Path;
+
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl AsRef<Path> for TempDir {
+  fn as_ref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}